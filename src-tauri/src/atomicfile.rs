@@ -0,0 +1,73 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Writes `contents` to a temporary sibling of `path`, fsyncs it and renames it over `path`, so
+/// readers only ever see the old or the new contents, never a truncated mix.
+pub fn write(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = tmp_path(path);
+
+    {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+
+    fs::rename(&tmp, path)?;
+    sync_parent_dir(path)
+}
+
+/// Shifts `path.1 .. path.{generations - 1}` up by one and links or copies `path` into
+/// `path.1`, dropping the oldest generation. `path` stays in place for [`write`] to replace, so
+/// readers never find it missing.
+pub fn rotate_backups(path: &Path, generations: usize) -> std::io::Result<()> {
+    if generations == 0 || !path.is_file() {
+        return Ok(());
+    }
+
+    for generation in (1..generations).rev() {
+        let older = backup_path(path, generation);
+        if older.is_file() {
+            fs::rename(&older, backup_path(path, generation + 1))?;
+        }
+    }
+
+    let newest = backup_path(path, 1);
+    if newest.is_file() {
+        fs::remove_file(&newest)?;
+    }
+    // a link costs nothing, copy where the filesystem has none
+    if fs::hard_link(path, &newest).is_err() {
+        fs::copy(path, &newest)?;
+    }
+
+    Ok(())
+}
+
+/// `data.json` -> `data.json.<generation>`
+pub fn backup_path(path: &Path, generation: usize) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{generation}"));
+    path.with_file_name(name)
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::File::open(dir)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,492 @@
+//! Portable library bundles: a tar archive holding a `manifest.json` and the audio of every item
+//! under `audio/`.
+//!
+//! The manifest has the shape of `data.json`, so bundles written by older versions are upgraded
+//! with the same migrations. Paths in it are relative to the archive, they are rewritten to the
+//! importing library's directory.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+use super::{
+    app_dir,
+    database::{schema, Collection, SharedDatabase},
+    unix_millis_now, AudioItem,
+};
+
+const MANIFEST: &str = "manifest.json";
+const AUDIO_DIR: &str = "audio";
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Manifest {
+    version: u32,
+    exported_at: u64,
+    items: BTreeMap<String, AudioItem>,
+    collections: BTreeMap<String, Collection>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ExportReport {
+    pub items_exported: usize,
+    /// ids of the items left out because their audio couldn't be read
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ArchiveImportReport {
+    /// ids of the items added to the library, after renaming
+    pub imported: Vec<String>,
+    /// archive id -> new id, for items whose id was already taken by a different item or by one
+    /// in the trash
+    pub renamed: BTreeMap<String, String>,
+    /// ids of items the library already had, only their tags were merged
+    pub already_present: Vec<String>,
+    /// ids of items in the manifest that had no audio in the archive
+    pub missing_audio: Vec<String>,
+}
+
+/// Writes the items with `ids`, or the whole library if none are given, to a tar archive at
+/// `dest`. Collections go along with the items of theirs that are exported. The database is
+/// only locked to take a snapshot, not while writing.
+pub fn export(
+    db: &SharedDatabase,
+    ids: Option<&[String]>,
+    dest: &Path,
+) -> anyhow::Result<ExportReport> {
+    let (items, collections) = {
+        let db = db.lock().unwrap();
+        (db.items(), db.collections())
+    };
+    let items: Vec<AudioItem> = match ids {
+        Some(ids) => items
+            .into_iter()
+            .filter(|item| ids.contains(&item.id))
+            .collect(),
+        None => items,
+    };
+
+    let partial = dest.with_extension("partial");
+    let report = write_archive(&partial, items, collections).and_then(|report| {
+        fs::rename(&partial, dest)
+            .with_context(|| format!("failed to move archive into place at {:?}", dest))?;
+        Ok(report)
+    });
+    if report.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+
+    report
+}
+
+fn write_archive(
+    path: &Path,
+    items: Vec<AudioItem>,
+    collections: Vec<Collection>,
+) -> anyhow::Result<ExportReport> {
+    let mut report = ExportReport::default();
+    let readable: Vec<AudioItem> = items
+        .into_iter()
+        .filter(|item| {
+            let is_readable = item.filepath.is_file();
+            if !is_readable {
+                eprintln!(
+                    "[warn] leaving {} out of the export, its audio is missing",
+                    item.id
+                );
+                report.skipped.push(item.id.clone());
+            }
+            is_readable
+        })
+        .collect();
+
+    let manifest = Manifest {
+        version: schema::CURRENT_VERSION,
+        exported_at: unix_millis_now(),
+        items: readable
+            .iter()
+            .map(|item| {
+                let mut item = item.clone();
+                item.filepath = archived_audio_path(&item.id);
+                item.is_playing = false;
+                (item.id.clone(), item)
+            })
+            .collect(),
+        collections: collections
+            .into_iter()
+            .filter_map(|mut collection| {
+                collection
+                    .item_ids
+                    .retain(|id| readable.iter().any(|item| &item.id == id));
+                (!collection.item_ids.is_empty()).then(|| (collection.id.clone(), collection))
+            })
+            .collect(),
+    };
+
+    let file = fs::File::create(path).with_context(|| format!("failed to create {:?}", path))?;
+    let mut builder = tar::Builder::new(io::BufWriter::new(file));
+
+    let manifest_json =
+        serde_json::to_vec_pretty(&manifest).context("failed to serialize manifest")?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest_json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(manifest.exported_at / 1000);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, MANIFEST, manifest_json.as_slice())
+        .context("failed to write manifest")?;
+
+    for item in readable {
+        builder
+            .append_path_with_name(&item.filepath, archived_audio_path(&item.id))
+            .with_context(|| format!("failed to archive {:?}", item.filepath))?;
+        report.items_exported += 1;
+    }
+
+    builder
+        .into_inner()
+        .and_then(|mut writer| io::Write::flush(&mut writer))
+        .context("failed to finish archive")?;
+
+    Ok(report)
+}
+
+/// Merges the archive at `path` into the library. An item whose id is taken is recognised as
+/// the same item if it has the same audio, or lacking content hashes, if it was created at the
+/// same time with the same duration. Otherwise, or if the item with its id is in the trash, it
+/// gets a fresh id. Collections are merged by name.
+pub fn import(db: &SharedDatabase, path: &Path) -> anyhow::Result<ArchiveImportReport> {
+    import_into(db, path, &app_dir())
+}
+
+fn import_into(
+    db: &SharedDatabase,
+    path: &Path,
+    library_dir: &Path,
+) -> anyhow::Result<ArchiveImportReport> {
+    let file = fs::File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+    let mut archive = tar::Archive::new(io::BufReader::new(file));
+    let mut entries = archive.entries().context("failed to read archive")?;
+
+    let manifest = match entries.next() {
+        Some(entry) => read_manifest(entry?)?,
+        None => bail!("archive is empty"),
+    };
+
+    let mut report = ArchiveImportReport::default();
+    // archive id -> id in this library
+    let mut ids: HashMap<String, String> = HashMap::new();
+    let mut pending = manifest.items;
+
+    for entry in entries {
+        let mut entry = entry.context("failed to read archive entry")?;
+        let entry_path = entry.path()?.into_owned();
+
+        let Some(archived_id) = archived_item_id(&entry_path) else {
+            eprintln!("[warn] ignoring unexpected archive entry {:?}", entry_path);
+            continue;
+        };
+        // a link would point into the library from wherever the archive says
+        if entry.header().entry_type() != tar::EntryType::Regular {
+            bail!("audio of {archived_id} in the archive is not a regular file");
+        }
+        let Some(mut item) = pending.remove(&archived_id) else {
+            eprintln!("[warn] ignoring audio of unknown item {archived_id}");
+            continue;
+        };
+        item.id = archived_id.clone();
+
+        let existing = db.lock().unwrap().get(&item.id);
+        match existing {
+            Some(mut existing)
+                if existing.deleted_at.is_none() && is_same_item(&existing, &item) =>
+            {
+                for tag in item.tags {
+                    if let Err(pos) = existing.tags.binary_search(&tag) {
+                        existing.tags.insert(pos, tag);
+                    }
+                }
+                db.lock().unwrap().save_audio_item(existing)?;
+
+                ids.insert(archived_id.clone(), archived_id.clone());
+                report.already_present.push(archived_id);
+                continue;
+            }
+            Some(_) => {
+                item.id = cuid2::cuid();
+                report.renamed.insert(archived_id.clone(), item.id.clone());
+            }
+            None => {}
+        }
+
+        item.filepath = library_dir.join(&item.id).with_extension("wav");
+        item.is_playing = false;
+        item.deleted_at = None;
+
+        unpack(&mut entry, &item.filepath)
+            .with_context(|| format!("failed to unpack audio of {archived_id}"))?;
+        if let Err(err) = db.lock().unwrap().save_audio_item(item.clone()) {
+            let _ = fs::remove_file(&item.filepath);
+            return Err(err);
+        }
+
+        ids.insert(archived_id, item.id.clone());
+        report.imported.push(item.id);
+    }
+
+    report.missing_audio = pending.into_keys().collect();
+
+    let mut db = db.lock().unwrap();
+    let mut existing_collections = db.collections();
+    for archived in manifest.collections.into_values() {
+        let collection = match existing_collections
+            .iter()
+            .find(|c| c.name == archived.name)
+        {
+            Some(collection) => collection.clone(),
+            None => {
+                let created = db.create_collection(archived.name)?;
+                existing_collections.push(created.clone());
+                created
+            }
+        };
+
+        for id in archived.item_ids.iter().filter_map(|id| ids.get(id)) {
+            db.add_to_collection(&collection.id, id)?;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Copies the entry's bytes rather than `tar::Entry::unpack`, which also creates links and sets
+/// permissions, into a new file, never through something already at `dest`.
+fn unpack(entry: &mut tar::Entry<impl io::Read>, dest: &Path) -> anyhow::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(dest)
+        .with_context(|| format!("failed to create {:?}", dest))?;
+
+    let copied = io::copy(entry, &mut file).and_then(|_| file.sync_all());
+    if copied.is_err() {
+        let _ = fs::remove_file(dest);
+    }
+    copied.with_context(|| format!("failed to write {:?}", dest))
+}
+
+fn read_manifest(mut entry: tar::Entry<impl io::Read>) -> anyhow::Result<Manifest> {
+    if entry.path()?.as_ref() != Path::new(MANIFEST) {
+        bail!("archive doesn't start with a {MANIFEST}");
+    }
+
+    let mut doc: serde_json::Value =
+        serde_json::from_reader(&mut entry).context("failed to parse manifest json")?;
+    schema::upgrade(&mut doc).context("failed to upgrade manifest")?;
+
+    serde_json::from_value(doc).context("failed to parse manifest")
+}
+
+fn archived_audio_path(id: &str) -> PathBuf {
+    Path::new(AUDIO_DIR).join(id).with_extension("wav")
+}
+
+/// Only `audio/<id>.wav` entries are taken, nothing in the archive decides where files go.
+fn archived_item_id(entry_path: &Path) -> Option<String> {
+    let mut components = entry_path.components();
+    let dir = components.next()?;
+    let file = Path::new(components.next()?.as_os_str());
+
+    if dir.as_os_str() != AUDIO_DIR || components.next().is_some() {
+        return None;
+    }
+    if file.extension()? != "wav" {
+        return None;
+    }
+
+    file.file_stem()
+        .and_then(|stem| stem.to_str())
+        .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()))
+        .map(str::to_owned)
+}
+
+fn is_same_item(a: &AudioItem, b: &AudioItem) -> bool {
+    match (a.content_hash.as_ref(), b.content_hash.as_ref()) {
+        (Some(a), Some(b)) => a == b,
+        _ => {
+            a.created_at.is_some() && a.created_at == b.created_at && a.duration_ms == b.duration_ms
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs, io,
+        path::Path,
+        sync::{Arc, Mutex},
+    };
+
+    use super::{archived_item_id, export, import_into, MANIFEST};
+    use crate::audio::{
+        database::{Database, FSDatabase, SharedDatabase},
+        AudioItem,
+    };
+
+    fn library(dir: &Path, items: Vec<AudioItem>) -> SharedDatabase {
+        let mut db = FSDatabase::open(dir.join("data.json"), dir).unwrap();
+        for mut item in items {
+            item.filepath = dir.join(&item.id).with_extension("wav");
+            fs::write(&item.filepath, format!("audio of {} in {:?}", item.id, dir)).unwrap();
+            db.save_audio_item(item).unwrap();
+        }
+        Arc::new(Mutex::new(Box::new(db)))
+    }
+
+    fn item(id: &str, content_hash: &str, tags: &[&str]) -> AudioItem {
+        let mut item = AudioItem::new(id.to_owned());
+        item.content_hash = Some(content_hash.to_owned());
+        item.tags = tags.iter().map(|tag| tag.to_string()).collect();
+        item
+    }
+
+    #[test]
+    fn it_merges_an_exported_library_into_another() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+        let source = library(
+            from.path(),
+            vec![
+                item("same", "h1", &["night", "voice"]),
+                item("clash", "h2", &[]),
+                item("binned", "h3", &[]),
+                item("fresh", "h4", &[]),
+            ],
+        );
+        let mut binned = item("binned", "h3", &[]);
+        binned.deleted_at = Some(1);
+        let target = library(
+            to.path(),
+            vec![
+                item("same", "h1", &["draft"]),
+                item("clash", "other", &[]),
+                binned,
+            ],
+        );
+        {
+            let mut source = source.lock().unwrap();
+            let summer = source.create_collection("Summer song".to_owned()).unwrap();
+            let demos = source.create_collection("Demos".to_owned()).unwrap();
+            for id in ["same", "clash", "fresh"] {
+                source.add_to_collection(&summer.id, id).unwrap();
+            }
+            source.add_to_collection(&demos.id, "binned").unwrap();
+
+            let mut target = target.lock().unwrap();
+            let summer = target.create_collection("Summer song".to_owned()).unwrap();
+            target.add_to_collection(&summer.id, "same").unwrap();
+        }
+
+        let bundle = from.path().join("bundle.tar");
+        assert_eq!(export(&source, None, &bundle).unwrap().items_exported, 4);
+        let report = import_into(&target, &bundle, to.path()).unwrap();
+
+        assert_eq!(report.already_present, ["same"]);
+        assert!(report.missing_audio.is_empty());
+        let renamed: Vec<&str> = report.renamed.keys().map(String::as_str).collect();
+        assert_eq!(renamed, ["binned", "clash"]);
+        let clash = &report.renamed["clash"];
+        let binned = &report.renamed["binned"];
+        assert_ne!(clash, "clash");
+        assert_ne!(binned, "binned");
+        assert_eq!(report.imported.len(), 3);
+
+        let db = target.lock().unwrap();
+        assert_eq!(db.get("same").unwrap().tags, ["draft", "night", "voice"]);
+        assert_eq!(
+            db.get("clash").unwrap().content_hash.as_deref(),
+            Some("other")
+        );
+        assert!(db.get("binned").unwrap().deleted_at.is_some());
+        for (archived, id) in [
+            ("clash", clash.as_str()),
+            ("binned", binned),
+            ("fresh", "fresh"),
+        ] {
+            let imported = db.get(id).unwrap();
+            assert_eq!(imported.deleted_at, None);
+            assert_eq!(imported.filepath, to.path().join(format!("{id}.wav")));
+            assert_eq!(
+                fs::read_to_string(&imported.filepath).unwrap(),
+                format!("audio of {archived} in {:?}", from.path())
+            );
+        }
+
+        let collections = db.collections();
+        assert_eq!(collections.len(), 2);
+        let summer = collections
+            .iter()
+            .find(|c| c.name == "Summer song")
+            .unwrap();
+        assert_eq!(summer.item_ids, ["same", clash.as_str(), "fresh"]);
+        let demos = collections.iter().find(|c| c.name == "Demos").unwrap();
+        assert_eq!(demos.item_ids, [binned.as_str()]);
+    }
+
+    #[test]
+    fn it_refuses_audio_that_is_a_link() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+        let source = library(from.path(), vec![item("linked", "h1", &[])]);
+        let bundle = from.path().join("bundle.tar");
+        export(&source, None, &bundle).unwrap();
+
+        // the same manifest, with a link in place of the audio
+        let mut archive = tar::Archive::new(fs::File::open(&bundle).unwrap());
+        let mut entries = archive.entries().unwrap();
+        let mut manifest = Vec::new();
+        io::copy(&mut entries.next().unwrap().unwrap(), &mut manifest).unwrap();
+        let linked = from.path().join("linked.tar");
+        let mut builder = tar::Builder::new(fs::File::create(&linked).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, MANIFEST, manifest.as_slice())
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "audio/linked.wav", "/etc/passwd")
+            .unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+
+        let target = library(to.path(), vec![]);
+        assert!(import_into(&target, &linked, to.path()).is_err());
+        assert!(fs::symlink_metadata(to.path().join("linked.wav")).is_err());
+        assert!(target.lock().unwrap().get("linked").is_none());
+    }
+
+    #[test]
+    fn it_only_takes_audio_entries_by_id() {
+        assert_eq!(
+            archived_item_id(Path::new("audio/ch72gsb320000udocl363eofy.wav")).as_deref(),
+            Some("ch72gsb320000udocl363eofy")
+        );
+        assert_eq!(
+            archived_item_id(Path::new("audio/../../etc/passwd.wav")),
+            None
+        );
+        assert_eq!(archived_item_id(Path::new("audio/nested/x.wav")), None);
+        assert_eq!(archived_item_id(Path::new("audio/x.mp3")), None);
+        assert_eq!(archived_item_id(Path::new("manifest.json")), None);
+    }
+}
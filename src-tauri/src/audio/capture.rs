@@ -0,0 +1,506 @@
+//! Getting recorded samples to disk. The input stream's callback only pushes samples into a
+//! ring buffer, a writer thread takes them out and appends them to the take's wav file. Memory
+//! use doesn't grow with the length of a take, and a crash loses at most the last second of it,
+//! the header is rewritten that often. Files left behind by a crash are adopted by the
+//! integrity check. The writer also meters what comes in, listens for voice in hands-free mode
+//! and keeps the last few seconds of input before a take, its pre-roll, to begin the take with.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, RecvTimeoutError, Sender},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+
+use crate::{
+    ringbuf,
+    settings::{HandsFree, MAX_PRE_ROLL_MS},
+};
+
+use super::{
+    duplicates::ContentHasher,
+    handsfree::{Voice, VoiceDetector},
+    meter::{Meter, SharedLevels, LEVEL_MS},
+};
+
+/// recordings have always been written with this gain
+const GAIN: f32 = 2.0;
+/// how long the writer waits for samples before looking again
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// how many frames are moved from the ring buffer to the file at a time
+const CHUNK_FRAMES: usize = 2048;
+
+/// Controls the writer thread of one input stream. The thread ends when this is dropped.
+pub struct Capture {
+    tx: Sender<Command>,
+}
+
+enum Command {
+    Start {
+        path: PathBuf,
+        reply: Sender<anyhow::Result<u64>>,
+    },
+    Stop {
+        reply: Sender<anyhow::Result<String>>,
+    },
+    Cancel {
+        reply: Sender<anyhow::Result<()>>,
+    },
+    Suspend {
+        is_suspended: bool,
+        reply: Sender<()>,
+    },
+    Detect(Option<HandsFree>),
+    PreRoll(u32),
+}
+
+impl Capture {
+    /// Starts the writer thread for samples in `spec` coming out of `samples`, publishing their
+    /// levels to `levels` and voice coming and going to `voice` while detecting it. Until a take
+    /// is started samples are thrown away, but for the pre-roll.
+    pub fn spawn(
+        mut samples: ringbuf::Consumer,
+        spec: hound::WavSpec,
+        levels: SharedLevels,
+        voice: Sender<Voice>,
+    ) -> Self {
+        let (tx, rx) = channel::<Command>();
+
+        thread::spawn(move || {
+            let mut chunk = vec![0.0; CHUNK_FRAMES * spec.channels as usize];
+            let mut writer = Writer {
+                spec,
+                take: None,
+                is_suspended: false,
+                pre_roll: VecDeque::new(),
+                pre_roll_len: 0,
+                meter: Meter::new(spec),
+                levels,
+                detector: None,
+                voice,
+            };
+            loop {
+                let wait = if samples.is_empty() {
+                    POLL_INTERVAL
+                } else {
+                    Duration::ZERO
+                };
+
+                match rx.recv_timeout(wait) {
+                    Ok(Command::Start { path, reply }) => {
+                        // up to now it's pre-roll
+                        writer.drain(&mut samples, &mut chunk);
+                        samples.take_dropped();
+
+                        let _ = reply.send(writer.start(&path));
+                    }
+                    Ok(Command::Stop { reply }) => {
+                        writer.drain(&mut samples, &mut chunk);
+                        let dropped = samples.take_dropped();
+                        if dropped > 0 && writer.take.is_some() {
+                            eprintln!("[warn] dropped {dropped} samples, the disk didn't keep up");
+                        }
+
+                        let _ = reply.send(writer.stop());
+                    }
+                    Ok(Command::Cancel { reply }) => {
+                        writer.drain(&mut samples, &mut chunk);
+                        samples.take_dropped();
+
+                        let _ = reply.send(writer.cancel());
+                    }
+                    Ok(Command::Suspend {
+                        is_suspended,
+                        reply,
+                    }) => {
+                        // what came in before belongs to the other side of the switch
+                        writer.drain(&mut samples, &mut chunk);
+                        writer.is_suspended = is_suspended;
+                        let _ = reply.send(());
+                    }
+                    Ok(Command::Detect(settings)) => {
+                        if let Some(change) = writer.detector.take().and_then(VoiceDetector::finish)
+                        {
+                            let _ = writer.voice.send(change);
+                        }
+                        writer.detector = settings.map(VoiceDetector::new);
+                    }
+                    Ok(Command::PreRoll(ms)) => {
+                        let ms = ms.min(MAX_PRE_ROLL_MS);
+                        let frames = spec.sample_rate as u64 * ms as u64 / 1000;
+                        writer.pre_roll_len = frames as usize * spec.channels as usize;
+                        writer.keep_for_pre_roll(&[]);
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+
+                let popped = samples.pop(&mut chunk);
+                writer.take_in(&mut chunk[..popped]);
+            }
+        });
+
+        Self { tx }
+    }
+
+    /// Starts writing samples to a new wav file at `path`, beginning with the pre-roll. Returns
+    /// how many milliseconds of pre-roll that were.
+    pub fn start(&self, path: &Path) -> anyhow::Result<u64> {
+        let (reply, response) = channel();
+        self.tx
+            .send(Command::Start {
+                path: path.to_path_buf(),
+                reply,
+            })
+            .context("the capture writer is gone")?;
+
+        response.recv().context("the capture writer is gone")?
+    }
+
+    /// Stops writing samples to the take until it is resumed, what comes in meanwhile is left
+    /// out of it. Returns once everything that came in before is written.
+    pub fn suspend(&self) {
+        self.set_suspended(true);
+    }
+
+    /// Returns once everything that came in while suspended is left out.
+    pub fn resume(&self) {
+        self.set_suspended(false);
+    }
+
+    fn set_suspended(&self, is_suspended: bool) {
+        let (reply, response) = channel();
+        if self
+            .tx
+            .send(Command::Suspend {
+                is_suspended,
+                reply,
+            })
+            .is_ok()
+        {
+            let _ = response.recv();
+        }
+    }
+
+    /// Ends the take without keeping any of it, its file is deleted.
+    pub fn cancel(&self) -> anyhow::Result<()> {
+        let (reply, response) = channel();
+        self.tx
+            .send(Command::Cancel { reply })
+            .context("the capture writer is gone")?;
+
+        response.recv().context("the capture writer is gone")?
+    }
+
+    /// Starts or, with none, stops listening for voice, with these settings.
+    pub fn detect(&self, settings: Option<HandsFree>) {
+        let _ = self.tx.send(Command::Detect(settings));
+    }
+
+    /// Keeps the last `ms` milliseconds of input from before a take to begin it with, at most
+    /// [`MAX_PRE_ROLL_MS`].
+    pub fn pre_roll(&self, ms: u32) {
+        let _ = self.tx.send(Command::PreRoll(ms));
+    }
+
+    /// Writes what's left of the take and finalizes its file. Returns the content hash of the
+    /// take's audio.
+    pub fn stop(&self) -> anyhow::Result<String> {
+        let (reply, response) = channel();
+        self.tx
+            .send(Command::Stop { reply })
+            .context("the capture writer is gone")?;
+
+        response.recv().context("the capture writer is gone")?
+    }
+}
+
+/// Everything the writer thread does with samples, in between commands.
+struct Writer {
+    spec: hound::WavSpec,
+    take: Option<TakeFile>,
+    /// the take is paused, nothing is written to it, nor is voice listened for
+    is_suspended: bool,
+    /// the latest input while there is no take, whole frames
+    pre_roll: VecDeque<f32>,
+    /// in samples
+    pre_roll_len: usize,
+    meter: Meter,
+    levels: SharedLevels,
+    detector: Option<VoiceDetector>,
+    voice: Sender<Voice>,
+}
+
+impl Writer {
+    /// Takes in everything queued up so far.
+    fn drain(&mut self, samples: &mut ringbuf::Consumer, chunk: &mut [f32]) {
+        loop {
+            let popped = samples.pop(chunk);
+            if popped == 0 {
+                break;
+            }
+            self.take_in(&mut chunk[..popped]);
+        }
+    }
+
+    /// Takes in whole frames off the ring buffer.
+    fn take_in(&mut self, chunk: &mut [f32]) {
+        if chunk.is_empty() {
+            return;
+        }
+
+        chunk.iter_mut().for_each(|sample| *sample *= GAIN);
+
+        let Self {
+            meter,
+            levels,
+            detector,
+            voice,
+            is_suspended,
+            ..
+        } = self;
+        meter.feed(chunk, |level| {
+            levels.publish(&level);
+            if let Some(change) = detector
+                .as_mut()
+                .filter(|_| !*is_suspended)
+                .and_then(|detector| detector.update(&level, LEVEL_MS))
+            {
+                let _ = voice.send(change);
+            }
+        });
+
+        match self.take.as_mut() {
+            Some(_) if self.is_suspended => {}
+            Some(take) => take.write(chunk),
+            None => self.keep_for_pre_roll(chunk),
+        }
+    }
+
+    fn keep_for_pre_roll(&mut self, samples: &[f32]) {
+        self.pre_roll.extend(samples);
+        let excess = self.pre_roll.len().saturating_sub(self.pre_roll_len);
+        self.pre_roll.drain(..excess);
+    }
+
+    fn start(&mut self, path: &Path) -> anyhow::Result<u64> {
+        let mut take = TakeFile::create(path, self.spec)?;
+
+        let (front, back) = self.pre_roll.as_slices();
+        take.write(front);
+        take.write(back);
+        let frames = (self.pre_roll.len() / self.spec.channels as usize) as u64;
+        self.pre_roll.clear();
+
+        self.take = Some(take);
+        self.is_suspended = false;
+        Ok(frames * 1000 / self.spec.sample_rate as u64)
+    }
+
+    fn stop(&mut self) -> anyhow::Result<String> {
+        match self.take.take() {
+            Some(take) => take.finish(),
+            None => Err(anyhow!("no take is being recorded")),
+        }
+    }
+
+    fn cancel(&mut self) -> anyhow::Result<()> {
+        match self.take.take() {
+            Some(take) => take.discard(),
+            None => Err(anyhow!("no take is being recorded")),
+        }
+    }
+}
+
+/// The wav file of the take being recorded.
+struct TakeFile {
+    path: PathBuf,
+    writer: hound::WavWriter<BufWriter<fs::File>>,
+    hasher: ContentHasher,
+    /// samples written since the header was last brought up to date
+    unflushed: usize,
+    /// a second of audio
+    flush_every: usize,
+    /// the first error writing the file, nothing more is written after it
+    error: Option<anyhow::Error>,
+}
+
+impl TakeFile {
+    fn create(path: &Path, spec: hound::WavSpec) -> anyhow::Result<Self> {
+        eprintln!("[info] writing wav with specs: {:?}", spec);
+        let writer = hound::WavWriter::create(path, spec)
+            .with_context(|| format!("failed to create {:?}", path))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer,
+            hasher: ContentHasher::new(spec),
+            unflushed: 0,
+            flush_every: spec.sample_rate as usize * spec.channels as usize,
+            error: None,
+        })
+    }
+
+    fn write(&mut self, samples: &[f32]) {
+        if self.error.is_some() || samples.is_empty() {
+            return;
+        }
+
+        if let Err(err) = self.try_write(samples) {
+            eprintln!("[err] {err:#}");
+            self.error = Some(err);
+        }
+    }
+
+    fn try_write(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        for sample in samples {
+            self.writer
+                .write_sample(*sample)
+                .with_context(|| format!("failed to write to {:?}", self.path))?;
+            self.hasher.update_f32(*sample);
+        }
+
+        self.unflushed += samples.len();
+        if self.unflushed >= self.flush_every {
+            self.writer
+                .flush()
+                .with_context(|| format!("failed to flush {:?}", self.path))?;
+            self.unflushed = 0;
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<String> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+
+        self.writer
+            .finalize()
+            .with_context(|| format!("failed to finalize {:?}", self.path))?;
+
+        Ok(self.hasher.finish())
+    }
+
+    fn discard(self) -> anyhow::Result<()> {
+        drop(self.writer);
+        fs::remove_file(&self.path).with_context(|| format!("failed to delete {:?}", self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        sync::mpsc::channel,
+    };
+
+    use tempfile::TempDir;
+
+    use super::{Capture, GAIN};
+    use crate::{
+        audio::{duplicates::content_hash, meter::SharedLevels},
+        ringbuf,
+    };
+
+    /// A capture of `f32` samples fed through a small ring, and the path in `TempDir` to record
+    /// its takes to.
+    fn spawn_capture(
+        channels: u16,
+        sample_rate: u32,
+    ) -> (Capture, ringbuf::Producer, PathBuf, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let (producer, consumer) = ringbuf::channel(64);
+        let (voice, _) = channel();
+        let capture = Capture::spawn(consumer, spec, SharedLevels::default(), voice);
+
+        (capture, producer, dir.path().join("take.wav"), dir)
+    }
+
+    fn read_samples(path: &Path) -> Vec<f32> {
+        hound::WavReader::open(path)
+            .unwrap()
+            .into_samples()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn it_writes_only_what_comes_in_during_a_take() {
+        let (capture, mut producer, path, _dir) = spawn_capture(1, 8000);
+
+        producer.push(&[0.4; 16]);
+        capture.start(&path).unwrap();
+        for i in 0..100 {
+            let sample = [i as f32 / 1000.0];
+            while !producer.push(&sample) {}
+        }
+        let hash = capture.stop().unwrap();
+
+        let samples = read_samples(&path);
+        assert_eq!(samples.len(), 100);
+        assert_eq!(samples[99], 0.099 * GAIN);
+        assert_eq!(hash, content_hash(&path).unwrap());
+        assert!(capture.stop().is_err());
+    }
+
+    #[test]
+    fn it_leaves_out_what_comes_in_while_suspended() {
+        let (capture, mut producer, path, _dir) = spawn_capture(1, 8000);
+
+        capture.start(&path).unwrap();
+        while !producer.push(&[0.1; 8]) {}
+        capture.suspend();
+        while !producer.push(&[0.2; 8]) {}
+        capture.resume();
+        while !producer.push(&[0.3; 8]) {}
+        capture.stop().unwrap();
+
+        assert_eq!(
+            read_samples(&path),
+            [[0.1 * GAIN; 8], [0.3 * GAIN; 8]].concat()
+        );
+
+        capture.start(&path).unwrap();
+        while !producer.push(&[0.4; 8]) {}
+        capture.cancel().unwrap();
+        assert!(!path.exists());
+        assert!(capture.cancel().is_err());
+    }
+
+    #[test]
+    fn it_begins_takes_with_the_pre_roll() {
+        // 2 frames per millisecond
+        let (capture, mut producer, path, _dir) = spawn_capture(2, 2000);
+
+        capture.pre_roll(5);
+        // a round trip, so that the writer keeps pre-roll by the time samples come in
+        assert!(capture.stop().is_err());
+        for i in 0..30 {
+            let frame = [i as f32 / 100.0; 2];
+            while !producer.push(&frame) {}
+        }
+        assert_eq!(capture.start(&path).unwrap(), 5);
+        while !producer.push(&[0.3; 2]) {}
+        capture.stop().unwrap();
+
+        let samples = read_samples(&path);
+        // the last 10 frames before the take, then the take
+        assert_eq!(samples.len(), 22);
+        assert_eq!(samples[0], 0.2 * GAIN);
+        assert_eq!(samples[19], 0.29 * GAIN);
+        assert_eq!(samples[21], 0.3 * GAIN);
+    }
+}
@@ -0,0 +1,638 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::{atomicfile, audio::AudioItem};
+
+use super::{schema, Collection, Database, RecoveryReport, RecoverySource, UpdateParams};
+
+const BACKUP_GENERATIONS: usize = 3;
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Data {
+    pub version: u32,
+    pub items: BTreeMap<String, AudioItem>,
+    pub collections: BTreeMap<String, Collection>,
+    /// the version the file was at before it got upgraded while reading it
+    #[serde(skip)]
+    pub migrated_from: Option<u32>,
+}
+
+impl Data {
+    fn empty() -> Self {
+        Data {
+            version: schema::CURRENT_VERSION,
+            items: BTreeMap::new(),
+            collections: BTreeMap::new(),
+            migrated_from: None,
+        }
+    }
+
+    /// Reads the data file, upgrading it to the current schema version (after backing it up) if
+    /// it was written by an older version. A missing data file is an empty library.
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Data::empty()),
+            Err(err) => {
+                return Err(err).context("failed to read from audio items data file");
+            }
+        };
+
+        Self::parse(path, &data)
+    }
+
+    /// Parses `data` read from the data file at `path`, see [`Data::read`].
+    fn parse(path: &Path, data: &str) -> anyhow::Result<Self> {
+        let mut doc: serde_json::Value =
+            serde_json::from_str(data).context("failed to parse audio items data file json")?;
+
+        let version = schema::version_of(&doc)?;
+        if version < schema::CURRENT_VERSION {
+            schema::backup_before_migrating(path, version)?;
+        }
+        schema::upgrade(&mut doc)?;
+
+        let mut data: Data =
+            serde_json::from_value(doc).context("failed to parse audio items data file json")?;
+        if version < schema::CURRENT_VERSION {
+            data.migrated_from = Some(version);
+        }
+
+        Ok(data)
+    }
+
+    /// Like [`Data::read`], but instead of failing on a damaged data file it falls back to the
+    /// newest backup that parses, and failing that, to an item per wav file in `wav_dir`. A data
+    /// file written by a newer build isn't damaged, it is left alone and the read fails.
+    pub fn read_or_recover(
+        path: &Path,
+        wav_dir: &Path,
+    ) -> anyhow::Result<(Self, Option<RecoveryReport>)> {
+        let reason = if path.exists() {
+            let err = match Self::read(path) {
+                Ok(data) => return Ok((data, None)),
+                Err(err) if err.is::<schema::NewerVersion>() => return Err(err),
+                Err(err) => err,
+            };
+
+            let corrupt = path.with_extension("json.corrupt");
+            if let Err(err) = fs::rename(path, &corrupt) {
+                eprintln!("[err] failed to set aside damaged data file: {err}");
+            }
+
+            format!("{err:#}")
+        } else if atomicfile::backup_path(path, 1).is_file() {
+            // the data file is gone, but its backups aren't
+            "audio items data file is missing".to_owned()
+        } else {
+            return Ok((Self::read(path)?, None));
+        };
+        eprintln!("[err] {reason}");
+
+        for generation in 1..=BACKUP_GENERATIONS {
+            let backup = atomicfile::backup_path(path, generation);
+            if !backup.is_file() {
+                continue;
+            }
+
+            match Self::read(&backup) {
+                Ok(data) => {
+                    let report = RecoveryReport {
+                        reason,
+                        source: RecoverySource::Backup(backup),
+                        items_recovered: data.items.len(),
+                    };
+                    return Ok((data, Some(report)));
+                }
+                Err(err) => eprintln!("[err] backup {:?} is unusable too: {err:#}", backup),
+            }
+        }
+
+        let data = Self::from_wav_files(wav_dir)?;
+        let report = RecoveryReport {
+            reason,
+            source: RecoverySource::WavFiles(wav_dir.to_path_buf()),
+            items_recovered: data.items.len(),
+        };
+
+        Ok((data, Some(report)))
+    }
+
+    /// Rebuilds bare items (no labels) from the `<id>.wav` files in `wav_dir`.
+    fn from_wav_files(wav_dir: &Path) -> anyhow::Result<Self> {
+        let mut items = BTreeMap::new();
+
+        for entry in fs::read_dir(wav_dir).context("failed to list wav files")? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "wav") {
+                let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+
+                let mut item = AudioItem::new(id.to_owned());
+                item.filepath = path.clone();
+                // unknown, the metadata backfill takes it from the file
+                item.created_at = None;
+                items.insert(item.id.clone(), item);
+            }
+        }
+
+        Ok(Data {
+            items,
+            ..Data::empty()
+        })
+    }
+}
+
+/// Keeps every item in memory and rewrites the whole `data.json` on each change. Changes
+/// someone else made to the file in the meantime are merged in first, rather than overwritten.
+pub struct FSDatabase {
+    datafile: PathBuf,
+    items: BTreeMap<String, AudioItem>,
+    collections: BTreeMap<String, Collection>,
+    recovery: Option<RecoveryReport>,
+    synced: Synced,
+}
+
+/// What the data file held when we last read or wrote it, to tell the changes others made to it
+/// apart from ours.
+struct Synced {
+    stamp: Option<FileStamp>,
+    items: BTreeMap<String, AudioItem>,
+    collections: BTreeMap<String, Collection>,
+}
+
+/// A hash of the data file's content, an edit can keep its length and land within the same
+/// mtime tick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FileStamp([u8; 32]);
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        fs::read(path).ok().map(|data| Self::of_data(&data))
+    }
+
+    fn of_data(data: &[u8]) -> Self {
+        Self(Sha256::digest(data).into())
+    }
+}
+
+impl FSDatabase {
+    pub fn open(datafile: PathBuf, wav_dir: &Path) -> anyhow::Result<Self> {
+        let (data, recovery) = Data::read_or_recover(&datafile, wav_dir)?;
+
+        let mut db = Self {
+            synced: Synced {
+                stamp: FileStamp::of(&datafile),
+                items: data.items.clone(),
+                collections: data.collections.clone(),
+            },
+            datafile,
+            items: data.items,
+            collections: data.collections,
+            recovery,
+        };
+
+        if let Some(report) = db.recovery.as_ref() {
+            eprintln!("[warn] {report}");
+            db.save_all()
+                .context("failed to save recovered audio items")?;
+        } else if let Some(version) = data.migrated_from {
+            eprintln!("[info] upgraded data file from version {version}");
+            db.save_all()
+                .context("failed to save upgraded audio items")?;
+        }
+
+        Ok(db)
+    }
+
+    /// Takes over what others changed in the data file since we last synced with it, unless we
+    /// changed the same item or collection too. Returns whether anything was taken over.
+    fn merge_external_changes(&mut self) -> anyhow::Result<bool> {
+        // not `Data::read`, a file that can't be read, or is gone for a moment, is no change at
+        // all rather than an empty library that would delete everything
+        let data = match fs::read(&self.datafile) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            data => data.context("failed to read externally changed data file")?,
+        };
+        let stamp = Some(FileStamp::of_data(&data));
+        if stamp == self.synced.stamp {
+            return Ok(false);
+        }
+
+        let theirs = String::from_utf8(data)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Data::parse(&self.datafile, &data))
+            .context("failed to read externally changed data file")?;
+        let taken = merge(&self.synced.items, &mut self.items, &theirs.items)
+            + merge(
+                &self.synced.collections,
+                &mut self.collections,
+                &theirs.collections,
+            );
+        for collection in self.collections.values_mut() {
+            collection.item_ids.retain(|id| self.items.contains_key(id));
+        }
+        if taken > 0 {
+            eprintln!("[warn] data file was changed outside of voechoal, merged {taken} changes");
+        }
+
+        self.synced = Synced {
+            stamp,
+            items: theirs.items,
+            collections: theirs.collections,
+        };
+
+        Ok(taken > 0)
+    }
+
+    fn save_all(&mut self) -> anyhow::Result<()> {
+        match self.merge_external_changes() {
+            Err(err) if err.is::<schema::NewerVersion>() => return Err(err),
+            Err(err) => eprintln!("[err] {err:#}, overwriting it"),
+            Ok(_) => {}
+        }
+
+        let data = json!({
+            "version": schema::CURRENT_VERSION,
+            "items": self.items,
+            "collections": self.collections
+        });
+        let json_string = serde_json::to_string(&data).context("failed to Serialize audio item")?;
+
+        atomicfile::rotate_backups(&self.datafile, BACKUP_GENERATIONS)
+            .context("failed to rotate data file backups")?;
+        atomicfile::write(&self.datafile, json_string.as_bytes())
+            .context("failed to write data file")?;
+
+        self.synced = Synced {
+            stamp: Some(FileStamp::of_data(json_string.as_bytes())),
+            items: self.items.clone(),
+            collections: self.collections.clone(),
+        };
+
+        Ok(())
+    }
+}
+
+/// Three-way merge of the records in `theirs` into `ours`, both changed from `base`. A record
+/// only one side changed takes that side's change, ours win where both did. Returns the number
+/// of records taken from `theirs`.
+fn merge<T: Clone + PartialEq>(
+    base: &BTreeMap<String, T>,
+    ours: &mut BTreeMap<String, T>,
+    theirs: &BTreeMap<String, T>,
+) -> usize {
+    let mut taken = 0;
+
+    for (id, their) in theirs {
+        let unchanged_by_us = base.get(id) == ours.get(id);
+        if unchanged_by_us && base.get(id) != Some(their) {
+            ours.insert(id.clone(), their.clone());
+            taken += 1;
+        }
+    }
+    for (id, base_record) in base {
+        let removed_by_them = !theirs.contains_key(id);
+        if removed_by_them && ours.get(id) == Some(base_record) {
+            ours.remove(id);
+            taken += 1;
+        }
+    }
+
+    taken
+}
+
+impl Database for FSDatabase {
+    fn reload(&mut self) -> anyhow::Result<bool> {
+        self.merge_external_changes()
+    }
+
+    fn get(&self, id: &str) -> Option<AudioItem> {
+        self.items.get(id).cloned()
+    }
+
+    fn items(&self) -> Vec<AudioItem> {
+        let mut items: Vec<AudioItem> = self
+            .items
+            .values()
+            .filter(|item| item.deleted_at.is_none())
+            .cloned()
+            .collect();
+        items.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        items
+    }
+
+    fn trashed_items(&self) -> Vec<AudioItem> {
+        self.items
+            .values()
+            .filter(|item| item.deleted_at.is_some())
+            .cloned()
+            .collect()
+    }
+
+    fn remove_item(&mut self, id: &str) -> anyhow::Result<bool> {
+        if self.items.remove(id).is_none() {
+            return Ok(false);
+        }
+        for collection in self.collections.values_mut() {
+            collection.item_ids.retain(|item_id| item_id != id);
+        }
+
+        self.save_all()
+            .context("failed to save removal of audio item in data file")?;
+
+        Ok(true)
+    }
+
+    fn save_audio_item(&mut self, item: AudioItem) -> anyhow::Result<()> {
+        self.items.insert(item.id.clone(), item);
+
+        self.save_all()
+            .context("failed to save new audio item in data file")?;
+
+        Ok(())
+    }
+
+    fn update_audio_items(&mut self, params: UpdateParams) -> anyhow::Result<bool> {
+        if let Some(item) = self.items.get_mut(params.id) {
+            item.is_playing = params.is_playing.unwrap_or(item.is_playing);
+            item.is_missing = params.is_missing.unwrap_or(item.is_missing);
+            item.filepath = params
+                .filepath
+                .map(|p| p.to_path_buf())
+                .unwrap_or(item.filepath.clone());
+            if let Some(e) = params.label {
+                item.label = Some(e);
+            }
+            if let Some(deleted_at) = params.deleted_at {
+                item.deleted_at = deleted_at;
+            }
+            if let Some(tags) = params.tags {
+                item.tags = tags;
+            }
+            if let Some(title) = params.title {
+                item.title = title;
+            }
+            if let Some(history) = params.title_history {
+                item.title_history = history;
+            }
+            item.is_favourite = params.is_favourite.unwrap_or(item.is_favourite);
+            if let Some(rating) = params.rating {
+                item.rating = rating;
+            }
+
+            self.save_all()?;
+            return Ok(true);
+        };
+
+        Ok(false)
+    }
+
+    fn collections(&self) -> Vec<Collection> {
+        self.collections.values().cloned().collect()
+    }
+
+    fn create_collection(&mut self, name: String) -> anyhow::Result<Collection> {
+        let collection = Collection {
+            id: cuid2::cuid(),
+            name,
+            item_ids: vec![],
+        };
+        self.collections
+            .insert(collection.id.clone(), collection.clone());
+
+        self.save_all()
+            .context("failed to save new collection in data file")?;
+
+        Ok(collection)
+    }
+
+    fn rename_collection(&mut self, id: &str, name: String) -> anyhow::Result<bool> {
+        let Some(collection) = self.collections.get_mut(id) else {
+            return Ok(false);
+        };
+        collection.name = name;
+
+        self.save_all()?;
+        Ok(true)
+    }
+
+    fn delete_collection(&mut self, id: &str) -> anyhow::Result<bool> {
+        if self.collections.remove(id).is_none() {
+            return Ok(false);
+        }
+
+        self.save_all()?;
+        Ok(true)
+    }
+
+    fn add_to_collection(&mut self, collection_id: &str, item_id: &str) -> anyhow::Result<bool> {
+        let Some(collection) = self.collections.get_mut(collection_id) else {
+            return Ok(false);
+        };
+        if !self.items.contains_key(item_id) {
+            return Ok(false);
+        }
+
+        if !collection.item_ids.iter().any(|id| id == item_id) {
+            collection.item_ids.push(item_id.to_owned());
+            self.save_all()?;
+        }
+
+        Ok(true)
+    }
+
+    fn remove_from_collection(
+        &mut self,
+        collection_id: &str,
+        item_id: &str,
+    ) -> anyhow::Result<bool> {
+        let Some(collection) = self.collections.get_mut(collection_id) else {
+            return Ok(false);
+        };
+
+        let len = collection.item_ids.len();
+        collection.item_ids.retain(|id| id != item_id);
+        if collection.item_ids.len() == len {
+            return Ok(false);
+        }
+
+        self.save_all()?;
+        Ok(true)
+    }
+
+    fn recovery_report(&self) -> Option<RecoveryReport> {
+        self.recovery.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{schema, Data, FSDatabase, RecoverySource};
+    use crate::{
+        atomicfile,
+        audio::{
+            database::{Database, UpdateParams},
+            AudioItem,
+        },
+    };
+
+    #[test]
+    fn it_leaves_a_data_file_of_a_newer_build_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let datafile = dir.path().join("data.json");
+        let contents = format!(
+            r#"{{"version": {}, "items": {{}}, "collections": {{}}, "unknown": true}}"#,
+            schema::CURRENT_VERSION + 1
+        );
+        fs::write(&datafile, &contents).unwrap();
+        fs::write(atomicfile::backup_path(&datafile, 1), r#"{"version": 0}"#).unwrap();
+
+        let err = FSDatabase::open(datafile.clone(), dir.path())
+            .err()
+            .unwrap();
+
+        assert!(err.is::<schema::NewerVersion>());
+        assert_eq!(fs::read_to_string(&datafile).unwrap(), contents);
+        assert!(!datafile.with_extension("json.corrupt").exists());
+
+        // nor is it overwritten when the newer build writes it while we have the library open
+        let other = dir.path().join("other.json");
+        let mut db = FSDatabase::open(other.clone(), dir.path()).unwrap();
+        fs::write(&other, &contents).unwrap();
+        assert!(db.save_audio_item(AudioItem::new("a".to_owned())).is_err());
+        assert_eq!(fs::read_to_string(&other).unwrap(), contents);
+    }
+
+    #[test]
+    fn it_recovers_from_newest_valid_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let datafile = dir.path().join("data.json");
+
+        fs::write(&datafile, r#"{"items": {"a": {"id": "a", "la"#).unwrap();
+        fs::write(dir.path().join("data.json.1"), "not json").unwrap();
+        fs::write(
+            dir.path().join("data.json.2"),
+            r#"{"items": {"a": {"id": "a", "label": "hey", "filepath": "/a.wav"}}}"#,
+        )
+        .unwrap();
+
+        let (data, report) = Data::read_or_recover(&datafile, dir.path()).unwrap();
+        let report = report.expect("should have recovered");
+
+        assert_eq!(data.items.len(), 1);
+        assert_eq!(data.items["a"].label.as_deref(), Some("hey"));
+        assert!(matches!(report.source, RecoverySource::Backup(p) if p.ends_with("data.json.2")));
+        assert!(dir.path().join("data.json.corrupt").is_file());
+    }
+
+    #[test]
+    fn it_rebuilds_from_wav_files_without_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let datafile = dir.path().join("data.json");
+
+        fs::write(&datafile, "").unwrap();
+        fs::write(dir.path().join("x1.wav"), "").unwrap();
+        fs::write(dir.path().join("x2.wav"), "").unwrap();
+
+        let (data, report) = Data::read_or_recover(&datafile, dir.path()).unwrap();
+
+        assert_eq!(data.items.len(), 2);
+        assert!(data.items["x1"].filepath.ends_with("x1.wav"));
+        assert!(matches!(
+            report.unwrap().source,
+            RecoverySource::WavFiles(_)
+        ));
+    }
+
+    #[test]
+    fn it_does_not_report_a_missing_data_file() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let (data, report) =
+            Data::read_or_recover(&dir.path().join("data.json"), dir.path()).unwrap();
+
+        assert!(data.items.is_empty());
+        assert!(report.is_none());
+    }
+
+    #[test]
+    fn it_merges_changes_made_by_another_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let datafile = dir.path().join("data.json");
+        let mut ours = FSDatabase::open(datafile.clone(), dir.path()).unwrap();
+        ours.save_audio_item(AudioItem::new("a".to_owned()))
+            .unwrap();
+        ours.save_audio_item(AudioItem::new("b".to_owned()))
+            .unwrap();
+
+        let mut theirs = FSDatabase::open(datafile.clone(), dir.path()).unwrap();
+        theirs
+            .save_audio_item(AudioItem::new("c".to_owned()))
+            .unwrap();
+        theirs.remove_item("b").unwrap();
+
+        ours.update_audio_items(UpdateParams {
+            id: "a",
+            label: Some(" Ours.".to_owned()),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut ids: Vec<String> = ours.items().into_iter().map(|item| item.id).collect();
+        ids.sort();
+        assert_eq!(ids, ["a", "c"]);
+
+        assert!(theirs.reload().unwrap());
+        assert_eq!(theirs.get("a").unwrap().label.as_deref(), Some(" Ours."));
+        assert!(!theirs.reload().unwrap());
+    }
+
+    #[test]
+    fn it_notices_a_change_that_keeps_the_length_and_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let datafile = dir.path().join("data.json");
+        let mut db = FSDatabase::open(datafile.clone(), dir.path()).unwrap();
+        let mut item = AudioItem::new("a".to_owned());
+        item.label = Some(" Ours.".to_owned());
+        db.save_audio_item(item).unwrap();
+
+        let modified = fs::metadata(&datafile).unwrap().modified().unwrap();
+        let data = fs::read_to_string(&datafile).unwrap();
+        fs::write(&datafile, data.replace(" Ours.", " Them.")).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&datafile)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        assert!(db.reload().unwrap());
+        assert_eq!(db.get("a").unwrap().label.as_deref(), Some(" Them."));
+    }
+
+    #[test]
+    fn it_keeps_everything_when_the_data_file_cannot_be_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let datafile = dir.path().join("data.json");
+        let mut db = FSDatabase::open(datafile.clone(), dir.path()).unwrap();
+        db.save_audio_item(AudioItem::new("a".to_owned())).unwrap();
+
+        assert!(Data::read(dir.path()).is_err());
+        // reading it fails, but it looks changed
+        fs::remove_file(&datafile).unwrap();
+        fs::create_dir(&datafile).unwrap();
+
+        assert!(db.reload().is_err());
+        assert!(db.get("a").is_some());
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use super::{
+    query::{RecordingsPage, RecordingsQuery},
+    AudioItem, TitleEdit,
+};
+
+mod json;
+pub mod schema;
+mod sqlite;
+
+pub use json::FSDatabase;
+pub use sqlite::SqliteDatabase;
+
+pub type SharedDatabase = Arc<Mutex<Box<dyn Database>>>;
+
+#[derive(Default)]
+pub struct UpdateParams<'i> {
+    pub id: &'i str,
+    pub filepath: Option<&'i Path>,
+    pub is_playing: Option<bool>,
+    pub is_missing: Option<bool>,
+    pub label: Option<String>,
+    /// `Some(None)` takes the item back out of the trash
+    pub deleted_at: Option<Option<u64>>,
+    /// replaces all of the item's tags
+    pub tags: Option<Vec<String>>,
+    /// `Some(None)` clears the title, leaving the transcript
+    pub title: Option<Option<String>>,
+    /// replaces the item's title history
+    pub title_history: Option<Vec<TitleEdit>>,
+    pub is_favourite: Option<bool>,
+    /// `Some(None)` clears the rating
+    pub rating: Option<Option<u8>>,
+}
+
+/// A named, ordered set of items, an item can be in any number of them.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Collection {
+    pub id: String,
+    pub name: String,
+    pub item_ids: Vec<String>,
+}
+
+/// Everything the audio workers and commands need from the place audio items are persisted.
+pub trait Database: Send {
+    fn get(&self, id: &str) -> Option<AudioItem>;
+
+    fn get_or_create(&self, id: &str) -> AudioItem {
+        self.get(id)
+            .unwrap_or_else(|| AudioItem::new(id.to_owned()))
+    }
+
+    /// Every item that isn't in the trash.
+    fn items(&self) -> Vec<AudioItem>;
+
+    fn trashed_items(&self) -> Vec<AudioItem>;
+
+    /// Deletes the item's record for good, returns false if there was none.
+    fn remove_item(&mut self, id: &str) -> anyhow::Result<bool>;
+
+    fn save_audio_item(&mut self, item: AudioItem) -> anyhow::Result<()>;
+
+    /// Returns false if there is no item with `params.id`.
+    fn update_audio_items(&mut self, params: UpdateParams) -> anyhow::Result<bool>;
+
+    fn collections(&self) -> Vec<Collection>;
+
+    fn create_collection(&mut self, name: String) -> anyhow::Result<Collection>;
+
+    /// Returns false if there is no collection with `id`.
+    fn rename_collection(&mut self, id: &str, name: String) -> anyhow::Result<bool>;
+
+    /// Deletes the collection but none of its items, returns false if there was none.
+    fn delete_collection(&mut self, id: &str) -> anyhow::Result<bool>;
+
+    /// Appends the item to the collection unless it's in there already. Returns false if
+    /// either doesn't exist.
+    fn add_to_collection(&mut self, collection_id: &str, item_id: &str) -> anyhow::Result<bool>;
+
+    /// Returns false if the item wasn't in the collection.
+    fn remove_from_collection(
+        &mut self,
+        collection_id: &str,
+        item_id: &str,
+    ) -> anyhow::Result<bool>;
+
+    /// Picks up changes other processes made to the library, returns whether there were any.
+    fn reload(&mut self) -> anyhow::Result<bool> {
+        Ok(false)
+    }
+
+    /// One page of the items matching `query`, `hits` being the ids matching its `text`, best
+    /// match first. None leaves it to [`query`](super::query::query) to filter `items()`.
+    fn query_page(
+        &self,
+        _query: &RecordingsQuery,
+        _hits: Option<&[String]>,
+        _limit: usize,
+    ) -> Option<anyhow::Result<RecordingsPage>> {
+        None
+    }
+
+    /// What had to be salvaged when the database was opened, if anything.
+    fn recovery_report(&self) -> Option<RecoveryReport> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub enum RecoverySource {
+    /// one of the rolling backups of the data file
+    Backup(PathBuf),
+    /// bare items rebuilt from the wav files in this directory
+    WavFiles(PathBuf),
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RecoveryReport {
+    pub reason: String,
+    pub source: RecoverySource,
+    pub items_recovered: usize,
+}
+
+impl std::fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            RecoverySource::Backup(path) => write!(
+                f,
+                "recovered {} audio items from backup {:?} ({})",
+                self.items_recovered, path, self.reason
+            ),
+            RecoverySource::WavFiles(dir) => write!(
+                f,
+                "rebuilt {} audio items from the wav files in {:?} ({})",
+                self.items_recovered, dir, self.reason
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Json,
+    Sqlite,
+}
+
+impl Backend {
+    /// Picked with `VOECHOAL_STORAGE=json|sqlite`, sqlite being the default.
+    pub fn from_env() -> Self {
+        match std::env::var("VOECHOAL_STORAGE").as_deref() {
+            Ok("json") => Backend::Json,
+            Ok("sqlite") | Err(_) => Backend::Sqlite,
+            Ok(other) => {
+                eprintln!("[warn] unknown storage backend {other:?}, using sqlite");
+                Backend::Sqlite
+            }
+        }
+    }
+}
+
+/// Opens the library in `dir`.
+pub fn open(backend: Backend, dir: &Path) -> anyhow::Result<Box<dyn Database>> {
+    match backend {
+        Backend::Json => Ok(Box::new(FSDatabase::open(
+            dir.join("data").with_extension("json"),
+            dir,
+        )?)),
+        Backend::Sqlite => {
+            let mut db = SqliteDatabase::open(&dir.join("library").with_extension("db"))?;
+            sqlite::migrate_json_data_file(&mut db, &dir.join("data").with_extension("json"), dir)?;
+            Ok(Box::new(db))
+        }
+    }
+}
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Keeps picking up changes made to the library by other processes.
+pub fn spawn_reloader(db: SharedDatabase) {
+    thread::spawn(move || loop {
+        thread::sleep(RELOAD_INTERVAL);
+
+        if let Err(err) = db.lock().unwrap().reload() {
+            eprintln!("[err] failed to reload the library: {err:#}");
+        }
+    });
+}
+
+pub fn wav_spec_from(config: &cpal::StreamConfig) -> hound::WavSpec {
+    hound::WavSpec {
+        channels: config.channels,
+        sample_rate: config.sample_rate.0,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    }
+}
@@ -0,0 +1,289 @@
+//! Versions of the `data.json` format and the steps that upgrade one version to the next.
+//!
+//! Every change to the persisted shape of `Data` or `AudioItem` gets a new version, a step in
+//! [`MIGRATIONS`] and a fixture under `tests/fixtures/data`.
+
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Context};
+use serde_json::{json, Map, Value};
+
+pub const CURRENT_VERSION: u32 = 11;
+
+type Migration = fn(&mut Value) -> anyhow::Result<()>;
+
+/// `MIGRATIONS[n]` upgrades a version `n` document to version `n + 1`.
+const MIGRATIONS: [Migration; CURRENT_VERSION as usize] = [
+    v0_to_v1, v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6, v6_to_v7, v7_to_v8, v8_to_v9,
+    v9_to_v10, v10_to_v11,
+];
+
+/// Files written before versioning have no `version` field, those are version 0.
+pub fn version_of(doc: &Value) -> anyhow::Result<u32> {
+    match doc.get("version") {
+        None => Ok(0),
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| anyhow!("invalid data file version: {v}")),
+    }
+}
+
+/// A data file written by a newer build, which must be left as it is.
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "data file version {version} is newer than this build supports ({CURRENT_VERSION}), \
+     refusing to touch it"
+)]
+pub struct NewerVersion {
+    pub version: u32,
+}
+
+/// Upgrades `doc` in place to [`CURRENT_VERSION`], returning the version it started at.
+pub fn upgrade(doc: &mut Value) -> anyhow::Result<u32> {
+    let from = version_of(doc)?;
+    if from > CURRENT_VERSION {
+        return Err(NewerVersion { version: from }.into());
+    }
+
+    for (version, migrate) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+        eprintln!("[info] migrating data file from version {version}");
+        migrate(doc).with_context(|| format!("failed to migrate data file from v{version}"))?;
+        doc["version"] = json!(version + 1);
+    }
+
+    Ok(from)
+}
+
+/// Copies the data file aside as `data.json.v<version>.bak` before it gets upgraded, keeping the
+/// first such copy if there already is one.
+pub fn backup_before_migrating(path: &Path, version: u32) -> anyhow::Result<()> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{version}.bak"));
+    let backup = path.with_file_name(name);
+
+    if !backup.exists() {
+        fs::copy(path, &backup)
+            .with_context(|| format!("failed to back up data file to {:?}", backup))?;
+    }
+
+    Ok(())
+}
+
+fn for_each_item(
+    doc: &mut Value,
+    mut f: impl FnMut(&mut Map<String, Value>),
+) -> anyhow::Result<()> {
+    let items = doc
+        .get_mut("items")
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| anyhow!("data file has no items object"))?;
+
+    for item in items.values_mut() {
+        f(item
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("audio item is not an object"))?);
+    }
+
+    Ok(())
+}
+
+/// v1 adds the `version` field and makes `is_playing`, which early files lack, explicit.
+fn v0_to_v1(doc: &mut Value) -> anyhow::Result<()> {
+    for_each_item(doc, |item| {
+        item.entry("is_playing").or_insert(json!(false));
+    })
+}
+
+/// v2 adds `deleted_at`, set on items that are in the trash.
+fn v1_to_v2(doc: &mut Value) -> anyhow::Result<()> {
+    for_each_item(doc, |item| {
+        item.entry("deleted_at").or_insert(Value::Null);
+    })
+}
+
+/// v3 adds the recording's metadata, left empty here and backfilled from the wav files.
+fn v2_to_v3(doc: &mut Value) -> anyhow::Result<()> {
+    for_each_item(doc, |item| {
+        for field in [
+            "created_at",
+            "duration_ms",
+            "sample_rate",
+            "channels",
+            "sample_format",
+            "file_size",
+        ] {
+            item.entry(field).or_insert(Value::Null);
+        }
+    })
+}
+
+/// v4 adds tags to items and the top level `collections`.
+fn v3_to_v4(doc: &mut Value) -> anyhow::Result<()> {
+    for_each_item(doc, |item| {
+        item.entry("tags").or_insert(json!([]));
+    })?;
+
+    doc.as_object_mut()
+        .ok_or_else(|| anyhow!("data file is not an object"))?
+        .entry("collections")
+        .or_insert(json!({}));
+
+    Ok(())
+}
+
+/// v5 adds `original_filename`, set on imported items.
+fn v4_to_v5(doc: &mut Value) -> anyhow::Result<()> {
+    for_each_item(doc, |item| {
+        item.entry("original_filename").or_insert(Value::Null);
+    })
+}
+
+/// v6 adds `is_missing`, set by the integrity check on items whose audio is gone.
+fn v5_to_v6(doc: &mut Value) -> anyhow::Result<()> {
+    for_each_item(doc, |item| {
+        item.entry("is_missing").or_insert(json!(false));
+    })
+}
+
+/// v7 adds `content_hash`, backfilled from the wav files.
+fn v6_to_v7(doc: &mut Value) -> anyhow::Result<()> {
+    for_each_item(doc, |item| {
+        item.entry("content_hash").or_insert(Value::Null);
+    })
+}
+
+/// v8 adds the user set `title` and its `title_history`.
+fn v7_to_v8(doc: &mut Value) -> anyhow::Result<()> {
+    for_each_item(doc, |item| {
+        item.entry("title").or_insert(Value::Null);
+        item.entry("title_history").or_insert(json!([]));
+    })
+}
+
+/// v9 adds `is_favourite` and the star `rating`.
+fn v8_to_v9(doc: &mut Value) -> anyhow::Result<()> {
+    for_each_item(doc, |item| {
+        item.entry("is_favourite").or_insert(json!(false));
+        item.entry("rating").or_insert(Value::Null);
+    })
+}
+
+/// v10 adds the `input_format` recordings were captured in, unknown for existing ones.
+fn v9_to_v10(doc: &mut Value) -> anyhow::Result<()> {
+    for_each_item(doc, |item| {
+        item.entry("input_format").or_insert(Value::Null);
+    })
+}
+
+/// v11 adds how much `pre_roll_ms` takes begin with, unknown for existing ones.
+fn v10_to_v11(doc: &mut Value) -> anyhow::Result<()> {
+    for_each_item(doc, |item| {
+        item.entry("pre_roll_ms").or_insert(Value::Null);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::CURRENT_VERSION;
+    use crate::audio::database::json::Data;
+
+    const FIXTURES: &[(&str, &str)] = &[
+        (
+            "v0-without-is-playing.json",
+            include_str!("../../../tests/fixtures/data/v0-without-is-playing.json"),
+        ),
+        (
+            "v0.json",
+            include_str!("../../../tests/fixtures/data/v0.json"),
+        ),
+        (
+            "v1.json",
+            include_str!("../../../tests/fixtures/data/v1.json"),
+        ),
+        (
+            "v2.json",
+            include_str!("../../../tests/fixtures/data/v2.json"),
+        ),
+        (
+            "v3.json",
+            include_str!("../../../tests/fixtures/data/v3.json"),
+        ),
+        (
+            "v4.json",
+            include_str!("../../../tests/fixtures/data/v4.json"),
+        ),
+        (
+            "v5.json",
+            include_str!("../../../tests/fixtures/data/v5.json"),
+        ),
+        (
+            "v6.json",
+            include_str!("../../../tests/fixtures/data/v6.json"),
+        ),
+        (
+            "v7.json",
+            include_str!("../../../tests/fixtures/data/v7.json"),
+        ),
+        (
+            "v8.json",
+            include_str!("../../../tests/fixtures/data/v8.json"),
+        ),
+        (
+            "v9.json",
+            include_str!("../../../tests/fixtures/data/v9.json"),
+        ),
+        (
+            "v10.json",
+            include_str!("../../../tests/fixtures/data/v10.json"),
+        ),
+        (
+            "v11.json",
+            include_str!("../../../tests/fixtures/data/v11.json"),
+        ),
+    ];
+
+    #[test]
+    fn it_loads_every_historical_version() {
+        for (name, contents) in FIXTURES {
+            let dir = tempfile::tempdir().unwrap();
+            let datafile = dir.path().join("data.json");
+            fs::write(&datafile, contents).unwrap();
+
+            let data = Data::read(&datafile).unwrap_or_else(|err| panic!("{name}: {err:#}"));
+
+            assert_eq!(data.version, CURRENT_VERSION, "{name}");
+            assert_eq!(data.items.len(), 2, "{name}");
+            let item = &data.items["ch72gsb320000udocl363eofy"];
+            assert_eq!(item.label.as_deref(), Some(" Hello there."), "{name}");
+            assert!(!item.is_playing, "{name}");
+        }
+    }
+
+    #[test]
+    fn it_backs_up_before_migrating() {
+        let dir = tempfile::tempdir().unwrap();
+        let datafile = dir.path().join("data.json");
+        fs::write(&datafile, FIXTURES[1].1).unwrap();
+
+        Data::read(&datafile).unwrap();
+
+        let backup = fs::read_to_string(dir.path().join("data.json.v0.bak")).unwrap();
+        assert_eq!(backup, FIXTURES[1].1);
+    }
+
+    #[test]
+    fn it_refuses_newer_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let datafile = dir.path().join("data.json");
+        fs::write(
+            &datafile,
+            format!(r#"{{"version": {}, "items": {{}}}}"#, CURRENT_VERSION + 1),
+        )
+        .unwrap();
+
+        assert!(Data::read(&datafile).is_err());
+    }
+}
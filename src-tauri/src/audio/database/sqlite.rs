@@ -0,0 +1,825 @@
+use std::{fs, path::Path, time::Duration};
+
+use anyhow::{bail, Context};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, ToSql};
+
+use crate::audio::{
+    polling::RecordingsSort,
+    query::{self, RecordingsPage, RecordingsQuery, TranscriptionStatus},
+    AudioItem, TitleEdit,
+};
+
+use super::{json::Data, Collection, Database, UpdateParams};
+
+/// how long to wait for another process to let go of the database before giving up
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `MIGRATIONS[n]` takes the schema from `PRAGMA user_version` n to n + 1.
+const MIGRATIONS: &[&str] = &[
+    r#"
+CREATE TABLE IF NOT EXISTS audio_items (
+    id          TEXT PRIMARY KEY NOT NULL,
+    label       TEXT,
+    filepath    TEXT NOT NULL,
+    is_playing  INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS audio_items_label ON audio_items (label);
+CREATE INDEX IF NOT EXISTS audio_items_is_playing ON audio_items (is_playing);
+"#,
+    r#"
+ALTER TABLE audio_items ADD COLUMN deleted_at INTEGER;
+CREATE INDEX audio_items_deleted_at ON audio_items (deleted_at);
+"#,
+    r#"
+ALTER TABLE audio_items ADD COLUMN created_at INTEGER;
+ALTER TABLE audio_items ADD COLUMN duration_ms INTEGER;
+ALTER TABLE audio_items ADD COLUMN sample_rate INTEGER;
+ALTER TABLE audio_items ADD COLUMN channels INTEGER;
+ALTER TABLE audio_items ADD COLUMN sample_format TEXT;
+ALTER TABLE audio_items ADD COLUMN file_size INTEGER;
+CREATE INDEX audio_items_created_at ON audio_items (created_at);
+CREATE INDEX audio_items_duration_ms ON audio_items (duration_ms);
+"#,
+    r#"
+CREATE TABLE item_tags (
+    item_id TEXT NOT NULL REFERENCES audio_items (id) ON DELETE CASCADE,
+    tag     TEXT NOT NULL,
+    PRIMARY KEY (item_id, tag)
+);
+CREATE INDEX item_tags_tag ON item_tags (tag);
+CREATE TABLE collections (
+    id      TEXT PRIMARY KEY NOT NULL,
+    name    TEXT NOT NULL
+);
+CREATE TABLE collection_items (
+    collection_id   TEXT NOT NULL REFERENCES collections (id) ON DELETE CASCADE,
+    item_id         TEXT NOT NULL REFERENCES audio_items (id) ON DELETE CASCADE,
+    position        INTEGER NOT NULL,
+    PRIMARY KEY (collection_id, item_id)
+);
+CREATE INDEX collection_items_item_id ON collection_items (item_id);
+"#,
+    r#"
+ALTER TABLE audio_items ADD COLUMN original_filename TEXT;
+"#,
+    r#"
+ALTER TABLE audio_items ADD COLUMN is_missing INTEGER NOT NULL DEFAULT 0;
+"#,
+    r#"
+ALTER TABLE audio_items ADD COLUMN content_hash TEXT;
+CREATE INDEX audio_items_content_hash ON audio_items (content_hash);
+"#,
+    r#"
+ALTER TABLE audio_items ADD COLUMN title TEXT;
+CREATE TABLE title_edits (
+    item_id     TEXT NOT NULL REFERENCES audio_items (id) ON DELETE CASCADE,
+    position    INTEGER NOT NULL,
+    title       TEXT,
+    edited_at   INTEGER NOT NULL,
+    PRIMARY KEY (item_id, position)
+);
+"#,
+    r#"
+ALTER TABLE audio_items ADD COLUMN is_favourite INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE audio_items ADD COLUMN rating INTEGER;
+CREATE INDEX audio_items_rating ON audio_items (rating);
+"#,
+    r#"
+ALTER TABLE audio_items ADD COLUMN input_format TEXT;
+"#,
+    r#"
+ALTER TABLE audio_items ADD COLUMN pre_roll_ms INTEGER;
+"#,
+];
+
+/// Items with their tags joined by the unit separator and their title history as a json array,
+/// so that one query yields whole items.
+const SELECT_ITEMS: &str = "SELECT audio_items.*,
+    (SELECT group_concat(tag, char(31)) FROM item_tags WHERE item_id = audio_items.id) AS tags,
+    (SELECT json_group_array(json_object('title', title, 'edited_at', edited_at)) FROM (
+        SELECT title, edited_at FROM title_edits
+        WHERE item_id = audio_items.id ORDER BY position
+    )) AS title_history
+    FROM audio_items";
+
+/// Stores audio items as rows, so a change only touches the row it is about.
+pub struct SqliteDatabase {
+    conn: Connection,
+    /// sqlite's count of commits made by other connections, as of the last reload
+    data_version: i64,
+}
+
+impl SqliteDatabase {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut conn = Connection::open(path)
+            .with_context(|| format!("failed to open sqlite database: {:?}", path))?;
+
+        conn.busy_timeout(BUSY_TIMEOUT)
+            .context("failed to set busy timeout")?;
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .context("failed to enable WAL journal mode")?;
+        conn.pragma_update(None, "foreign_keys", true)
+            .context("failed to enable foreign keys")?;
+        Self::migrate(&mut conn, path)?;
+        let data_version = Self::data_version(&conn)?;
+
+        Ok(Self { conn, data_version })
+    }
+
+    fn data_version(conn: &Connection) -> rusqlite::Result<i64> {
+        conn.pragma_query_value(None, "data_version", |row| row.get(0))
+    }
+
+    fn migrate(conn: &mut Connection, path: &Path) -> anyhow::Result<()> {
+        let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            bail!(
+                "sqlite schema version {version} is newer than this build supports ({}), \
+                 refusing to touch it",
+                MIGRATIONS.len()
+            );
+        }
+        if version == MIGRATIONS.len() {
+            return Ok(());
+        }
+
+        let has_tables: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table')",
+            [],
+            |row| row.get(0),
+        )?;
+        if has_tables {
+            let backup = path.with_extension(format!("db.v{version}.bak"));
+            if !backup.exists() {
+                conn.execute("VACUUM INTO ?1", [backup.to_string_lossy()])
+                    .with_context(|| {
+                        format!("failed to back up sqlite database to {:?}", backup)
+                    })?;
+            }
+        }
+
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            eprintln!("[info] migrating sqlite schema from version {version}");
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)
+                .with_context(|| format!("failed to migrate sqlite schema from v{version}"))?;
+            tx.pragma_update(None, "user_version", version + 1)?;
+            tx.commit()?;
+        }
+
+        Ok(())
+    }
+
+    fn item_from_row(row: &rusqlite::Row) -> rusqlite::Result<AudioItem> {
+        Ok(AudioItem {
+            id: row.get("id")?,
+            label: row.get("label")?,
+            filepath: row.get::<_, String>("filepath")?.into(),
+            is_playing: row.get("is_playing")?,
+            deleted_at: row.get("deleted_at")?,
+            created_at: row.get("created_at")?,
+            duration_ms: row.get("duration_ms")?,
+            sample_rate: row.get("sample_rate")?,
+            channels: row.get("channels")?,
+            sample_format: row.get("sample_format")?,
+            file_size: row.get("file_size")?,
+            tags: row
+                .get::<_, Option<String>>("tags")?
+                .map(|tags| tags.split('\u{1f}').map(str::to_owned).collect())
+                .unwrap_or_default(),
+            original_filename: row.get("original_filename")?,
+            is_missing: row.get("is_missing")?,
+            content_hash: row.get("content_hash")?,
+            title: row.get("title")?,
+            is_favourite: row.get("is_favourite")?,
+            rating: row.get("rating")?,
+            input_format: row.get("input_format")?,
+            pre_roll_ms: row.get("pre_roll_ms")?,
+            title_history: serde_json::from_str(&row.get::<_, String>("title_history")?).map_err(
+                |err| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        0,
+                        rusqlite::types::Type::Text,
+                        Box::new(err),
+                    )
+                },
+            )?,
+        })
+    }
+
+    fn find(&self, id: &str) -> anyhow::Result<Option<AudioItem>> {
+        let item = self
+            .conn
+            .query_row(
+                &format!("{SELECT_ITEMS} WHERE id = ?1"),
+                [id],
+                Self::item_from_row,
+            )
+            .optional()?;
+
+        Ok(item)
+    }
+
+    /// None are selected if the query fails, e.g. because another process kept the database
+    /// busy for longer than [`BUSY_TIMEOUT`].
+    fn select(&self, sql: &str) -> Vec<AudioItem> {
+        let items = self.conn.prepare_cached(sql).and_then(|mut stmt| {
+            let items = stmt.query_map([], Self::item_from_row)?.collect();
+            items
+        });
+
+        items.unwrap_or_else(|err| {
+            eprintln!("[err] failed to query audio items: {err}");
+            vec![]
+        })
+    }
+
+    /// Filters, sorts and pages in sql, so only the rows of the page are read. Ties are broken the
+    /// way the in-memory path breaks them: by rank, then in `items()` order.
+    fn select_page(
+        &self,
+        query: &RecordingsQuery,
+        hits: Option<&[String]>,
+        limit: usize,
+    ) -> anyhow::Result<RecordingsPage> {
+        let mut args: Vec<Box<dyn ToSql>> = Vec::new();
+        let mut bind = |value: Box<dyn ToSql>| {
+            args.push(value);
+            format!("?{}", args.len())
+        };
+
+        let mut with = String::new();
+        let mut join = "";
+        let mut filters = vec!["deleted_at IS NULL".to_owned()];
+        let mut order = match query.sort {
+            Some(RecordingsSort::Oldest) => vec!["created_at IS NULL, created_at"],
+            Some(RecordingsSort::Newest) => vec!["created_at DESC"],
+            Some(RecordingsSort::Rating) => vec!["rating DESC, created_at DESC"],
+            Some(RecordingsSort::FavouritesFirst) => vec!["is_favourite DESC, created_at DESC"],
+            Some(RecordingsSort::Longest) => vec!["duration_ms DESC"],
+            None => vec![],
+        };
+
+        if let Some(hits) = hits {
+            let hits = bind(Box::new(serde_json::to_string(hits)?));
+            with =
+                format!("WITH hits (hit_id, rank) AS (SELECT value, key FROM json_each({hits})) ");
+            join = " JOIN hits ON hit_id = audio_items.id";
+            order.push("rank");
+        }
+        order.push("created_at, audio_items.id");
+
+        if let Some(collection_id) = query.collection.as_ref() {
+            let exists: bool = self.conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM collections WHERE id = ?1)",
+                [collection_id],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                bail!("no collection with id {collection_id}");
+            }
+            filters.push(format!(
+                "audio_items.id IN (SELECT item_id FROM collection_items WHERE collection_id = {})",
+                bind(Box::new(collection_id.clone()))
+            ));
+        }
+        for tag in &query.tags {
+            filters.push(format!(
+                "EXISTS (SELECT 1 FROM item_tags WHERE item_id = audio_items.id AND tag = {})",
+                bind(Box::new(tag.clone()))
+            ));
+        }
+        if let Some(after) = query.created_after {
+            filters.push(format!("created_at >= {}", bind(Box::new(after))));
+        }
+        if let Some(before) = query.created_before {
+            filters.push(format!("created_at < {}", bind(Box::new(before))));
+        }
+        if let Some(min) = query.min_duration_ms {
+            filters.push(format!("duration_ms >= {}", bind(Box::new(min))));
+        }
+        if let Some(max) = query.max_duration_ms {
+            filters.push(format!("duration_ms <= {}", bind(Box::new(max))));
+        }
+        match query.transcription {
+            Some(TranscriptionStatus::Pending) => filters.push("label IS NULL".to_owned()),
+            Some(TranscriptionStatus::Transcribed) => filters.push("label IS NOT NULL".to_owned()),
+            None => {}
+        }
+        if query.favourites_only {
+            filters.push("is_favourite".to_owned());
+        }
+        if let Some(min) = query.min_rating {
+            filters.push(format!("rating >= {}", bind(Box::new(min))));
+        }
+
+        // after `FROM audio_items`
+        let filtered = format!("{join} WHERE {}", filters.join(" AND "));
+        let order = order.join(", ");
+        let n = args.len();
+
+        let total: usize = self.conn.query_row(
+            &format!("{with}SELECT COUNT(*) FROM audio_items{filtered}"),
+            params_from_iter(&args),
+            |row| row.get(0),
+        )?;
+
+        let start = match query.cursor.as_deref() {
+            Some(cursor) => {
+                let (offset, id) = query::parse_cursor(cursor)?;
+                let position: Option<usize> = self
+                    .conn
+                    .query_row(
+                        &format!(
+                            "{with}SELECT position FROM (
+                                SELECT audio_items.id AS item_id,
+                                    ROW_NUMBER() OVER (ORDER BY {order}) AS position
+                                FROM audio_items{filtered}
+                            ) WHERE item_id = ?{}",
+                            n + 1
+                        ),
+                        params_from_iter(
+                            args.iter()
+                                .chain([&(Box::new(id.to_owned()) as Box<dyn ToSql>)]),
+                        ),
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                position.unwrap_or(offset.min(total))
+            }
+            None => 0,
+        };
+
+        let page = [Box::new(limit) as Box<dyn ToSql>, Box::new(start)];
+        let mut stmt = self.conn.prepare(&format!(
+            "{with}{SELECT_ITEMS}{filtered} ORDER BY {order} LIMIT ?{} OFFSET ?{}",
+            n + 1,
+            n + 2
+        ))?;
+        let items: Vec<AudioItem> = stmt
+            .query_map(
+                params_from_iter(args.iter().chain(&page)),
+                Self::item_from_row,
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let end = start + items.len();
+        let next_cursor = items
+            .last()
+            .filter(|_| end < total)
+            .map(|last| query::cursor(end, last));
+
+        Ok(RecordingsPage {
+            items,
+            total,
+            next_cursor,
+        })
+    }
+
+    fn is_empty(&self) -> anyhow::Result<bool> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM audio_items", [], |row| row.get(0))?;
+
+        Ok(count == 0)
+    }
+
+    fn insert_all<'i>(&mut self, items: impl Iterator<Item = &'i AudioItem>) -> anyhow::Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            // not `INSERT OR REPLACE`, that would delete the row and cascade to its tags and
+            // collection memberships
+            let mut stmt = tx.prepare(
+                "INSERT INTO audio_items (
+                    id, label, filepath, is_playing, deleted_at, created_at,
+                    duration_ms, sample_rate, channels, sample_format, file_size,
+                    original_filename, is_missing, content_hash, title, is_favourite, rating,
+                    input_format, pre_roll_ms
+                 )
+                 VALUES (
+                    ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+                    ?18, ?19
+                 )
+                 ON CONFLICT (id) DO UPDATE SET
+                    label = excluded.label,
+                    filepath = excluded.filepath,
+                    is_playing = excluded.is_playing,
+                    deleted_at = excluded.deleted_at,
+                    created_at = excluded.created_at,
+                    duration_ms = excluded.duration_ms,
+                    sample_rate = excluded.sample_rate,
+                    channels = excluded.channels,
+                    sample_format = excluded.sample_format,
+                    file_size = excluded.file_size,
+                    original_filename = excluded.original_filename,
+                    is_missing = excluded.is_missing,
+                    content_hash = excluded.content_hash,
+                    title = excluded.title,
+                    is_favourite = excluded.is_favourite,
+                    rating = excluded.rating,
+                    input_format = excluded.input_format,
+                    pre_roll_ms = excluded.pre_roll_ms",
+            )?;
+            for item in items {
+                stmt.execute(params![
+                    item.id,
+                    item.label,
+                    item.filepath.to_string_lossy(),
+                    item.is_playing,
+                    item.deleted_at,
+                    item.created_at,
+                    item.duration_ms,
+                    item.sample_rate,
+                    item.channels,
+                    item.sample_format,
+                    item.file_size,
+                    item.original_filename,
+                    item.is_missing,
+                    item.content_hash,
+                    item.title,
+                    item.is_favourite,
+                    item.rating,
+                    item.input_format,
+                    item.pre_roll_ms
+                ])?;
+                Self::replace_tags(&tx, &item.id, &item.tags)?;
+                Self::replace_title_history(&tx, &item.id, &item.title_history)?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn replace_tags(conn: &Connection, item_id: &str, tags: &[String]) -> rusqlite::Result<()> {
+        conn.execute("DELETE FROM item_tags WHERE item_id = ?1", [item_id])?;
+
+        let mut stmt =
+            conn.prepare_cached("INSERT OR IGNORE INTO item_tags (item_id, tag) VALUES (?1, ?2)")?;
+        for tag in tags {
+            stmt.execute([item_id, tag])?;
+        }
+
+        Ok(())
+    }
+
+    fn replace_title_history(
+        conn: &Connection,
+        item_id: &str,
+        history: &[TitleEdit],
+    ) -> rusqlite::Result<()> {
+        conn.execute("DELETE FROM title_edits WHERE item_id = ?1", [item_id])?;
+
+        let mut stmt = conn.prepare_cached(
+            "INSERT INTO title_edits (item_id, position, title, edited_at) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for (position, edit) in history.iter().enumerate() {
+            stmt.execute(params![item_id, position, edit.title, edit.edited_at])?;
+        }
+
+        Ok(())
+    }
+
+    fn insert_collections(
+        &mut self,
+        collections: impl Iterator<Item = Collection>,
+    ) -> anyhow::Result<()> {
+        let tx = self.conn.transaction()?;
+        for collection in collections {
+            tx.execute(
+                "INSERT OR IGNORE INTO collections (id, name) VALUES (?1, ?2)",
+                [&collection.id, &collection.name],
+            )?;
+            for (position, item_id) in collection.item_ids.iter().enumerate() {
+                tx.execute(
+                    "INSERT OR IGNORE INTO collection_items (collection_id, item_id, position)
+                     VALUES (?1, ?2, ?3)",
+                    params![collection.id, item_id, position],
+                )?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+}
+
+impl Database for SqliteDatabase {
+    /// Rows are always read fresh, there is only something to tell if another process committed
+    /// to the database.
+    fn reload(&mut self) -> anyhow::Result<bool> {
+        let data_version = Self::data_version(&self.conn)?;
+        let changed = data_version != self.data_version;
+        self.data_version = data_version;
+
+        Ok(changed)
+    }
+
+    fn get(&self, id: &str) -> Option<AudioItem> {
+        self.find(id).unwrap_or_else(|err| {
+            eprintln!("[err] failed to query audio item {id}: {err:#}");
+            None
+        })
+    }
+
+    fn query_page(
+        &self,
+        query: &RecordingsQuery,
+        hits: Option<&[String]>,
+        limit: usize,
+    ) -> Option<anyhow::Result<RecordingsPage>> {
+        Some(self.select_page(query, hits, limit))
+    }
+
+    fn items(&self) -> Vec<AudioItem> {
+        self.select(&format!(
+            "{SELECT_ITEMS} WHERE deleted_at IS NULL ORDER BY created_at, id"
+        ))
+    }
+
+    fn trashed_items(&self) -> Vec<AudioItem> {
+        self.select(&format!(
+            "{SELECT_ITEMS} WHERE deleted_at IS NOT NULL ORDER BY deleted_at"
+        ))
+    }
+
+    fn remove_item(&mut self, id: &str) -> anyhow::Result<bool> {
+        let removed = self
+            .conn
+            .execute("DELETE FROM audio_items WHERE id = ?1", [id])
+            .context("failed to delete audio item")?;
+
+        Ok(removed > 0)
+    }
+
+    fn save_audio_item(&mut self, item: AudioItem) -> anyhow::Result<()> {
+        self.insert_all(std::iter::once(&item))
+            .context("failed to save new audio item in sqlite database")
+    }
+
+    fn update_audio_items(&mut self, params: UpdateParams) -> anyhow::Result<bool> {
+        let tx = self.conn.transaction()?;
+        let changed = tx.execute(
+            "UPDATE audio_items SET
+                is_playing = COALESCE(?2, is_playing),
+                filepath = COALESCE(?3, filepath),
+                label = COALESCE(?4, label),
+                deleted_at = CASE WHEN ?5 THEN ?6 ELSE deleted_at END,
+                is_missing = COALESCE(?7, is_missing),
+                title = CASE WHEN ?8 THEN ?9 ELSE title END,
+                is_favourite = COALESCE(?10, is_favourite),
+                rating = CASE WHEN ?11 THEN ?12 ELSE rating END
+             WHERE id = ?1",
+            params![
+                params.id,
+                params.is_playing,
+                params.filepath.map(|p| p.to_string_lossy()),
+                params.label,
+                params.deleted_at.is_some(),
+                params.deleted_at.flatten(),
+                params.is_missing,
+                params.title.is_some(),
+                params.title.clone().flatten(),
+                params.is_favourite,
+                params.rating.is_some(),
+                params.rating.flatten()
+            ],
+        )?;
+
+        if let (Some(tags), true) = (params.tags, changed > 0) {
+            Self::replace_tags(&tx, params.id, &tags)?;
+        }
+        if let (Some(history), true) = (params.title_history, changed > 0) {
+            Self::replace_title_history(&tx, params.id, &history)?;
+        }
+        tx.commit()?;
+
+        Ok(changed > 0)
+    }
+
+    fn collections(&self) -> Vec<Collection> {
+        let collections = self
+            .conn
+            .prepare_cached(
+                "SELECT id, name,
+                    (SELECT group_concat(item_id, char(31)) FROM (
+                        SELECT item_id FROM collection_items
+                        WHERE collection_id = collections.id ORDER BY position
+                    )) AS item_ids
+                 FROM collections ORDER BY name",
+            )
+            .and_then(|mut stmt| {
+                let collections = stmt
+                    .query_map([], |row| {
+                        Ok(Collection {
+                            id: row.get("id")?,
+                            name: row.get("name")?,
+                            item_ids: row
+                                .get::<_, Option<String>>("item_ids")?
+                                .map(|ids| ids.split('\u{1f}').map(str::to_owned).collect())
+                                .unwrap_or_default(),
+                        })
+                    })?
+                    .collect();
+                collections
+            });
+
+        collections.unwrap_or_else(|err| {
+            eprintln!("[err] failed to query collections: {err}");
+            vec![]
+        })
+    }
+
+    fn create_collection(&mut self, name: String) -> anyhow::Result<Collection> {
+        let collection = Collection {
+            id: cuid2::cuid(),
+            name,
+            item_ids: vec![],
+        };
+
+        self.conn
+            .execute(
+                "INSERT INTO collections (id, name) VALUES (?1, ?2)",
+                [&collection.id, &collection.name],
+            )
+            .context("failed to create collection")?;
+
+        Ok(collection)
+    }
+
+    fn rename_collection(&mut self, id: &str, name: String) -> anyhow::Result<bool> {
+        let changed = self.conn.execute(
+            "UPDATE collections SET name = ?2 WHERE id = ?1",
+            [id, &name],
+        )?;
+
+        Ok(changed > 0)
+    }
+
+    fn delete_collection(&mut self, id: &str) -> anyhow::Result<bool> {
+        let changed = self
+            .conn
+            .execute("DELETE FROM collections WHERE id = ?1", [id])?;
+
+        Ok(changed > 0)
+    }
+
+    fn add_to_collection(&mut self, collection_id: &str, item_id: &str) -> anyhow::Result<bool> {
+        let result = self.conn.execute(
+            "INSERT OR IGNORE INTO collection_items (collection_id, item_id, position)
+             VALUES (?1, ?2, (
+                SELECT COALESCE(MAX(position) + 1, 0) FROM collection_items
+                WHERE collection_id = ?1
+             ))",
+            [collection_id, item_id],
+        );
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn remove_from_collection(
+        &mut self,
+        collection_id: &str,
+        item_id: &str,
+    ) -> anyhow::Result<bool> {
+        let changed = self.conn.execute(
+            "DELETE FROM collection_items WHERE collection_id = ?1 AND item_id = ?2",
+            [collection_id, item_id],
+        )?;
+
+        Ok(changed > 0)
+    }
+}
+
+/// Copies the items of a legacy `data.json` into an empty database, then renames the file so
+/// that it's only ever imported once.
+pub fn migrate_json_data_file(
+    db: &mut SqliteDatabase,
+    datafile: &Path,
+    wav_dir: &Path,
+) -> anyhow::Result<()> {
+    if !datafile.is_file() || !db.is_empty()? {
+        return Ok(());
+    }
+
+    eprintln!("[info] migrating {:?} into sqlite", datafile);
+    let (data, recovery) = Data::read_or_recover(datafile, wav_dir)?;
+    if let Some(report) = recovery {
+        eprintln!("[warn] {report}");
+    }
+    db.insert_all(data.items.values())
+        .context("failed to migrate audio items into sqlite")?;
+    db.insert_collections(data.collections.into_values())
+        .context("failed to migrate collections into sqlite")?;
+
+    if !datafile.is_file() {
+        // recovery set the damaged file aside already
+        return Ok(());
+    }
+    let migrated = datafile.with_extension("json.migrated");
+    fs::rename(datafile, &migrated)
+        .with_context(|| format!("failed to rename {:?} to {:?}", datafile, migrated))?;
+    eprintln!("[info] migrated {} audio items", data.items.len());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{migrate_json_data_file, SqliteDatabase};
+    use crate::audio::database::{Database, UpdateParams};
+
+    #[test]
+    fn it_persists_changes_across_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("library.db");
+
+        {
+            let mut db = SqliteDatabase::open(&path).unwrap();
+            let item = db.get_or_create("a");
+            db.save_audio_item(item).unwrap();
+            let updated = db
+                .update_audio_items(UpdateParams {
+                    id: "a",
+                    is_playing: Some(true),
+                    label: Some("hey".to_owned()),
+                    ..Default::default()
+                })
+                .unwrap();
+            assert!(updated);
+        }
+
+        let db = SqliteDatabase::open(&path).unwrap();
+        let items = db.items();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].label.as_deref(), Some("hey"));
+        assert!(items[0].is_playing);
+    }
+
+    #[test]
+    fn it_notices_changes_made_by_another_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("library.db");
+        let mut ours = SqliteDatabase::open(&path).unwrap();
+        let mut theirs = SqliteDatabase::open(&path).unwrap();
+
+        ours.save_audio_item(ours.get_or_create("a")).unwrap();
+        assert!(!ours.reload().unwrap());
+
+        theirs.save_audio_item(theirs.get_or_create("b")).unwrap();
+        assert!(ours.reload().unwrap());
+        assert!(!ours.reload().unwrap());
+        assert_eq!(ours.items().len(), 2);
+    }
+
+    #[test]
+    fn it_keeps_tags_and_collections_when_an_item_is_saved_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = SqliteDatabase::open(&dir.path().join("library.db")).unwrap();
+
+        let mut item = db.get_or_create("a");
+        item.tags = vec!["verse".to_owned(), "idea".to_owned()];
+        db.save_audio_item(item).unwrap();
+        let collection = db.create_collection("Summer song".to_owned()).unwrap();
+        assert!(db.add_to_collection(&collection.id, "a").unwrap());
+        assert!(!db.add_to_collection(&collection.id, "nope").unwrap());
+
+        let mut item = db.get("a").unwrap();
+        item.label = Some("hey".to_owned());
+        db.save_audio_item(item).unwrap();
+
+        assert_eq!(db.get("a").unwrap().tags, ["idea", "verse"]);
+        assert_eq!(db.collections()[0].item_ids, ["a"]);
+
+        db.remove_item("a").unwrap();
+        assert!(db.collections()[0].item_ids.is_empty());
+    }
+
+    #[test]
+    fn it_migrates_a_legacy_data_file_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let datafile = dir.path().join("data.json");
+        fs::write(
+            &datafile,
+            include_str!("../../../tests/fixtures/data/v0.json"),
+        )
+        .unwrap();
+
+        let mut db = SqliteDatabase::open(&dir.path().join("library.db")).unwrap();
+        migrate_json_data_file(&mut db, &datafile, dir.path()).unwrap();
+
+        assert_eq!(db.items().len(), 2);
+        assert!(!datafile.exists());
+        assert!(dir.path().join("data.json.migrated").is_file());
+    }
+}
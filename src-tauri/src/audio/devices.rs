@@ -0,0 +1,175 @@
+//! Input devices to record from. The one picked in the settings is looked up by name, as that
+//! is all that stays the same across restarts and replugging.
+//!
+//! Devices deliver samples in whatever format they like, they are converted to `f32` as they
+//! come in.
+
+use anyhow::{anyhow, bail, Context};
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, Sample, SampleFormat, SizedSample,
+};
+
+use super::audio_stream_err_fn;
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct InputDevice {
+    pub name: String,
+    /// the system's default input
+    pub is_default: bool,
+    pub configs: Vec<InputConfig>,
+}
+
+/// A range of sample rates the device can record at with the same channels and format.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct InputConfig {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    /// e.g. `i16`
+    pub sample_format: String,
+}
+
+/// Every input device of the default host, with what it supports.
+pub fn list() -> anyhow::Result<Vec<InputDevice>> {
+    let host = cpal::default_host();
+    let default_name = host.default_input_device().and_then(|mic| mic.name().ok());
+
+    let devices = host
+        .input_devices()
+        .context("failed to list input devices")?
+        .filter_map(|mic| {
+            let name = mic.name().ok()?;
+            let configs = match mic.supported_input_configs() {
+                Ok(configs) => configs
+                    .map(|config| InputConfig {
+                        channels: config.channels(),
+                        min_sample_rate: config.min_sample_rate().0,
+                        max_sample_rate: config.max_sample_rate().0,
+                        sample_format: config.sample_format().to_string(),
+                    })
+                    .collect(),
+                Err(err) => {
+                    eprintln!("[warn] failed to get configs of input device {name:?}: {err}");
+                    vec![]
+                }
+            };
+
+            Some(InputDevice {
+                is_default: default_name.as_ref() == Some(&name),
+                name,
+                configs,
+            })
+        })
+        .collect();
+
+    Ok(devices)
+}
+
+/// The input device named `name`, the system's default one if none.
+pub fn input_device(name: Option<&str>) -> anyhow::Result<cpal::Device> {
+    let host = cpal::default_host();
+
+    let Some(name) = name else {
+        return host
+            .default_input_device()
+            .ok_or_else(|| anyhow!("no input device available, is a microphone connected?"));
+    };
+
+    host.input_devices()
+        .context("failed to list input devices")?
+        .find(|mic| mic.name().is_ok_and(|n| n == name))
+        .ok_or_else(|| anyhow!("input device {name:?} is not connected"))
+}
+
+/// Builds a paused stream recording `format` samples from `mic`, handing them to `on_data` as
+/// `f32`.
+pub fn build_input_stream(
+    mic: &cpal::Device,
+    config: &cpal::StreamConfig,
+    format: SampleFormat,
+    on_data: impl FnMut(&[f32]) + Send + 'static,
+) -> anyhow::Result<cpal::Stream> {
+    let stream = match format {
+        SampleFormat::I8 => build_converting::<i8>(mic, config, on_data),
+        SampleFormat::I16 => build_converting::<i16>(mic, config, on_data),
+        SampleFormat::I32 => build_converting::<i32>(mic, config, on_data),
+        SampleFormat::I64 => build_converting::<i64>(mic, config, on_data),
+        SampleFormat::U8 => build_converting::<u8>(mic, config, on_data),
+        SampleFormat::U16 => build_converting::<u16>(mic, config, on_data),
+        SampleFormat::U32 => build_converting::<u32>(mic, config, on_data),
+        SampleFormat::U64 => build_converting::<u64>(mic, config, on_data),
+        SampleFormat::F32 => {
+            let mut on_data = on_data;
+            mic.build_input_stream(
+                config,
+                move |data: &[f32], _| on_data(data),
+                audio_stream_err_fn,
+                None,
+            )
+        }
+        SampleFormat::F64 => build_converting::<f64>(mic, config, on_data),
+        format => bail!("can't record {format} samples"),
+    }
+    .with_context(|| format!("failed to build {format} input stream"))?;
+
+    stream.pause().context("failed to pause the input stream")?;
+
+    Ok(stream)
+}
+
+fn build_converting<T>(
+    mic: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut on_data: impl FnMut(&[f32]) + Send + 'static,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    // reused across callbacks, so that converting doesn't allocate once it has grown
+    let mut converted = Vec::new();
+
+    mic.build_input_stream(
+        config,
+        move |data: &[T], _| {
+            convert_into(data, &mut converted);
+            on_data(&converted);
+        },
+        audio_stream_err_fn,
+        None,
+    )
+}
+
+/// Replaces what's in `out` with `samples` scaled to -1.0..1.0.
+fn convert_into<T>(samples: &[T], out: &mut Vec<f32>)
+where
+    T: Sample,
+    f32: FromSample<T>,
+{
+    out.clear();
+    out.extend(samples.iter().map(|sample| sample.to_sample::<f32>()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::convert_into;
+
+    #[test]
+    fn it_converts_every_format_to_the_same_range() {
+        let mut out = vec![];
+
+        convert_into(&[i16::MIN, 0, i16::MAX / 2], &mut out);
+        assert_eq!(out, [-1.0, 0.0, 0.49996948]);
+
+        convert_into(&[0u16, 32768, u16::MAX], &mut out);
+        assert_eq!(out[..2], [-1.0, 0.0]);
+        assert!((out[2] - 1.0).abs() < 1e-4);
+
+        convert_into(&[i32::MIN, 0], &mut out);
+        assert_eq!(out, [-1.0, 0.0]);
+
+        convert_into(&[128u8], &mut out);
+        assert_eq!(out, [0.0]);
+    }
+}
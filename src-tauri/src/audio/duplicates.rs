@@ -0,0 +1,213 @@
+//! Finding items with identical audio under different ids, and merging them into one.
+//!
+//! The content hash covers the audio's format and samples but not the rest of the wav header,
+//! so a rewritten header doesn't change it.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Context};
+use sha2::{Digest, Sha256};
+
+use super::{database::Database, metadata, trash, AudioItem};
+
+/// Hashes samples as they are written, the same way [`content_hash`] reads them back.
+pub struct ContentHasher(Sha256);
+
+impl ContentHasher {
+    pub fn new(spec: hound::WavSpec) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(spec.channels.to_le_bytes());
+        hasher.update(spec.sample_rate.to_le_bytes());
+        hasher.update(metadata::sample_format_name(spec).as_bytes());
+
+        Self(hasher)
+    }
+
+    pub fn update_f32(&mut self, sample: f32) {
+        self.0.update(sample.to_le_bytes());
+    }
+
+    /// hex encoded
+    pub fn finish(self) -> String {
+        format!("{:x}", self.0.finalize())
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DuplicateGroup {
+    pub content_hash: String,
+    /// oldest first
+    pub ids: Vec<String>,
+}
+
+/// Hashes the audio of the wav file at `path`.
+pub fn content_hash(path: &Path) -> anyhow::Result<String> {
+    let file = fs::File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+    let reader = hound::WavReader::new(BufReader::new(file))
+        .with_context(|| format!("failed to read wav header of {:?}", path))?;
+    let spec = reader.spec();
+    let data_len = reader.len() as u64 * (spec.bits_per_sample as u64).div_ceil(8);
+
+    let mut hasher = ContentHasher::new(spec);
+    // the reader is left at the start of the samples
+    io::copy(&mut reader.into_inner().take(data_len), &mut hasher.0)
+        .with_context(|| format!("failed to read {:?}", path))?;
+
+    Ok(hasher.finish())
+}
+
+/// Groups of items outside of the trash that have the same audio.
+pub fn find(db: &dyn Database) -> Vec<DuplicateGroup> {
+    let mut by_hash: BTreeMap<String, Vec<AudioItem>> = BTreeMap::new();
+    for item in db.items() {
+        if let Some(hash) = item.content_hash.clone() {
+            by_hash.entry(hash).or_default().push(item);
+        }
+    }
+
+    by_hash
+        .into_iter()
+        .filter(|(_, items)| items.len() > 1)
+        .map(|(content_hash, mut items)| {
+            items.sort_by_key(|item| (item.created_at, item.id.clone()));
+            DuplicateGroup {
+                content_hash,
+                ids: items.into_iter().map(|item| item.id).collect(),
+            }
+        })
+        .collect()
+}
+
+/// Folds the labels, tags and collection memberships of `duplicates` into the item with id
+/// `keep`, taking the first title if it has none, then moves the duplicates to the trash.
+/// Refuses if any of them has different audio.
+pub fn merge(
+    db: &mut dyn Database,
+    keep: &str,
+    duplicates: &[String],
+) -> anyhow::Result<AudioItem> {
+    let mut kept = db
+        .get(keep)
+        .ok_or_else(|| anyhow!("no audio item with id {keep}"))?;
+    let Some(hash) = kept.content_hash.clone() else {
+        bail!("audio item {keep} has not been hashed yet");
+    };
+
+    let others = duplicates
+        .iter()
+        .filter(|id| id.as_str() != keep)
+        .map(|id| {
+            let item = db
+                .get(id)
+                .ok_or_else(|| anyhow!("no audio item with id {id}"))?;
+            if item.content_hash.as_ref() != Some(&hash) {
+                bail!("audio item {id} is not a duplicate of {keep}");
+            }
+            Ok(item)
+        })
+        .collect::<anyhow::Result<Vec<AudioItem>>>()?;
+
+    for other in others.iter() {
+        kept.label = merge_labels(kept.label.take(), other.label.clone());
+        if kept.title.is_none() && other.title.is_some() {
+            kept.title = other.title.clone();
+            kept.title_history = other.title_history.clone();
+        }
+        for tag in other.tags.iter() {
+            if let Err(pos) = kept.tags.binary_search(tag) {
+                kept.tags.insert(pos, tag.clone());
+            }
+        }
+    }
+    db.save_audio_item(kept.clone())?;
+
+    let collections = db.collections();
+    for other in others {
+        for collection in collections
+            .iter()
+            .filter(|c| c.item_ids.contains(&other.id))
+        {
+            db.add_to_collection(&collection.id, keep)?;
+        }
+        trash::move_to_trash(db, &other.id)?;
+    }
+
+    Ok(kept)
+}
+
+/// Both labels, unless they say the same.
+fn merge_labels(a: Option<String>, b: Option<String>) -> Option<String> {
+    let a = a.filter(|a| !a.trim().is_empty());
+    let b = b.filter(|b| !b.trim().is_empty());
+
+    match (a, b) {
+        (Some(a), Some(b)) if a.trim() != b.trim() => {
+            Some(format!("{} / {}", a.trim_end(), b.trim()))
+        }
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{content_hash, merge_labels, ContentHasher};
+
+    #[test]
+    fn it_hashes_written_audio_like_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let samples: Vec<f32> = (0..960).map(|i| (i as f32 / 40.0).sin()).collect();
+
+        let write = |name: &str, spec: hound::WavSpec| {
+            let path = dir.path().join(name);
+            let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+            let mut hasher = ContentHasher::new(spec);
+            for sample in samples.iter() {
+                writer.write_sample(*sample).unwrap();
+                hasher.update_f32(*sample);
+            }
+            writer.finalize().unwrap();
+            (content_hash(&path).unwrap(), hasher.finish())
+        };
+
+        let (a, written) = write("a.wav", spec);
+        let (b, _) = write("b.wav", spec);
+        let (resampled, _) = write(
+            "c.wav",
+            hound::WavSpec {
+                sample_rate: 44100,
+                ..spec
+            },
+        );
+
+        assert_eq!(a, written);
+        assert_eq!(a, b);
+        assert_ne!(a, resampled);
+    }
+
+    #[test]
+    fn it_keeps_both_labels_unless_equal() {
+        let label = |s: &str| Some(s.to_owned());
+
+        assert_eq!(
+            merge_labels(label(" Hello there."), label(" Hello there.")),
+            label(" Hello there.")
+        );
+        assert_eq!(
+            merge_labels(label(" Hello there."), label(" La la la.")),
+            label(" Hello there. / La la la.")
+        );
+        assert_eq!(merge_labels(None, label(" La la la.")), label(" La la la."));
+        assert_eq!(merge_labels(label(" "), None), None);
+    }
+}
@@ -0,0 +1,348 @@
+//! Changes to the library pushed to the webview as they happen, instead of it polling.
+//!
+//! Every event carries the next number of one sequence. A client that sees a gap in it missed
+//! events and takes a fresh [`Snapshot`], which tells the sequence number it is current up to.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, RwLock,
+};
+
+use super::{
+    database::{Collection, Database, RecoveryReport, UpdateParams},
+    ecouter::{self, RecordState},
+    query::{RecordingsPage, RecordingsQuery},
+    stt, AudioItem,
+};
+
+/// Name of the tauri event the changes are emitted as.
+pub const CHANGE_EVENT: &str = "recordings-changed";
+
+pub type SharedEventBus = Arc<EventBus>;
+
+type Sink = Box<dyn Fn(&ChangeEvent) + Send + Sync>;
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Change {
+    ItemCreated {
+        item: AudioItem,
+    },
+    /// any change but to the label or playback, including moving in and out of the trash
+    ItemUpdated {
+        item: AudioItem,
+    },
+    ItemRemoved {
+        id: String,
+    },
+    LabelUpdated {
+        id: String,
+        label: Option<String>,
+    },
+    PlaybackStarted {
+        id: String,
+    },
+    PlaybackStopped {
+        id: String,
+    },
+    TranscriptionStarted {
+        id: String,
+    },
+    TranscriptionFinished {
+        id: String,
+    },
+    /// a take started, was suspended, resumed or ended, by hand or hands-free
+    RecordStateChanged {
+        state: RecordState,
+    },
+    CollectionsChanged {
+        collections: Vec<Collection>,
+    },
+    /// another library was opened, everything has changed
+    LibrarySwitched,
+    /// the library was changed by another process, anything may have changed
+    Reloaded,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ChangeEvent {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Snapshot {
+    /// the sequence number of the last change this snapshot includes
+    pub seq: u64,
+    pub is_transcribing: bool,
+    pub record_state: RecordState,
+    pub audio_items: Vec<AudioItem>,
+    pub collections: Vec<Collection>,
+}
+
+/// Numbers changes and hands them to the sink, which is set once the app can emit events.
+#[derive(Default)]
+pub struct EventBus {
+    seq: AtomicU64,
+    sink: RwLock<Option<Sink>>,
+}
+
+impl EventBus {
+    pub fn set_sink(&self, sink: impl Fn(&ChangeEvent) + Send + Sync + 'static) {
+        *self.sink.write().unwrap() = Some(Box::new(sink));
+    }
+
+    pub fn emit(&self, change: Change) {
+        let event = ChangeEvent {
+            seq: self.seq.fetch_add(1, Ordering::SeqCst) + 1,
+            change,
+        };
+
+        if let Some(sink) = self.sink.read().unwrap().as_ref() {
+            sink(&event);
+        }
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq.load(Ordering::SeqCst)
+    }
+
+    /// Takes `db` as a lock guard's contents, so that no change can slip in between reading the
+    /// sequence number and the items.
+    pub fn snapshot(&self, db: &dyn Database) -> Snapshot {
+        Snapshot {
+            seq: self.seq(),
+            is_transcribing: stt::IS_TRANSCRIBING.load(Ordering::Relaxed),
+            record_state: ecouter::RECORD_STATE.read().unwrap().clone(),
+            audio_items: db.items(),
+            collections: db.collections(),
+        }
+    }
+}
+
+/// Wraps a database to emit a change event for everything that changes through it.
+pub struct Notifying {
+    inner: Box<dyn Database>,
+    events: SharedEventBus,
+}
+
+impl Notifying {
+    pub fn new(inner: Box<dyn Database>, events: SharedEventBus) -> Self {
+        Self { inner, events }
+    }
+
+    fn emit_diff(&self, before: Option<AudioItem>, id: &str) {
+        let Some(after) = self.inner.get(id) else {
+            return;
+        };
+        let Some(before) = before else {
+            self.events.emit(Change::ItemCreated { item: after });
+            return;
+        };
+
+        if before.label != after.label {
+            self.events.emit(Change::LabelUpdated {
+                id: id.to_owned(),
+                label: after.label.clone(),
+            });
+        }
+        if before.is_playing != after.is_playing {
+            self.events.emit(if after.is_playing {
+                Change::PlaybackStarted { id: id.to_owned() }
+            } else {
+                Change::PlaybackStopped { id: id.to_owned() }
+            });
+        }
+
+        let mut rest = before;
+        rest.label = after.label.clone();
+        rest.is_playing = after.is_playing;
+        if rest != after {
+            self.events.emit(Change::ItemUpdated { item: after });
+        }
+    }
+
+    fn emit_collections(&self) {
+        self.events.emit(Change::CollectionsChanged {
+            collections: self.inner.collections(),
+        });
+    }
+}
+
+impl Database for Notifying {
+    fn reload(&mut self) -> anyhow::Result<bool> {
+        let reloaded = self.inner.reload()?;
+        if reloaded {
+            self.events.emit(Change::Reloaded);
+        }
+        Ok(reloaded)
+    }
+
+    fn get(&self, id: &str) -> Option<AudioItem> {
+        self.inner.get(id)
+    }
+
+    fn items(&self) -> Vec<AudioItem> {
+        self.inner.items()
+    }
+
+    fn trashed_items(&self) -> Vec<AudioItem> {
+        self.inner.trashed_items()
+    }
+
+    fn remove_item(&mut self, id: &str) -> anyhow::Result<bool> {
+        let removed = self.inner.remove_item(id)?;
+        if removed {
+            self.events.emit(Change::ItemRemoved { id: id.to_owned() });
+        }
+        Ok(removed)
+    }
+
+    fn save_audio_item(&mut self, item: AudioItem) -> anyhow::Result<()> {
+        let id = item.id.clone();
+        let before = self.inner.get(&id);
+
+        self.inner.save_audio_item(item)?;
+        self.emit_diff(before, &id);
+        Ok(())
+    }
+
+    fn update_audio_items(&mut self, params: UpdateParams) -> anyhow::Result<bool> {
+        let id = params.id;
+        let before = self.inner.get(id);
+
+        let updated = self.inner.update_audio_items(params)?;
+        if updated {
+            self.emit_diff(before, id);
+        }
+        Ok(updated)
+    }
+
+    fn collections(&self) -> Vec<Collection> {
+        self.inner.collections()
+    }
+
+    fn create_collection(&mut self, name: String) -> anyhow::Result<Collection> {
+        let collection = self.inner.create_collection(name)?;
+        self.emit_collections();
+        Ok(collection)
+    }
+
+    fn rename_collection(&mut self, id: &str, name: String) -> anyhow::Result<bool> {
+        let renamed = self.inner.rename_collection(id, name)?;
+        if renamed {
+            self.emit_collections();
+        }
+        Ok(renamed)
+    }
+
+    fn delete_collection(&mut self, id: &str) -> anyhow::Result<bool> {
+        let deleted = self.inner.delete_collection(id)?;
+        if deleted {
+            self.emit_collections();
+        }
+        Ok(deleted)
+    }
+
+    fn add_to_collection(&mut self, collection_id: &str, item_id: &str) -> anyhow::Result<bool> {
+        let added = self.inner.add_to_collection(collection_id, item_id)?;
+        if added {
+            self.emit_collections();
+        }
+        Ok(added)
+    }
+
+    fn remove_from_collection(
+        &mut self,
+        collection_id: &str,
+        item_id: &str,
+    ) -> anyhow::Result<bool> {
+        let removed = self.inner.remove_from_collection(collection_id, item_id)?;
+        if removed {
+            self.emit_collections();
+        }
+        Ok(removed)
+    }
+
+    fn query_page(
+        &self,
+        query: &RecordingsQuery,
+        hits: Option<&[String]>,
+        limit: usize,
+    ) -> Option<anyhow::Result<RecordingsPage>> {
+        self.inner.query_page(query, hits, limit)
+    }
+
+    fn recovery_report(&self) -> Option<RecoveryReport> {
+        self.inner.recovery_report()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{EventBus, Notifying};
+    use crate::audio::{
+        database::{Database, FSDatabase, UpdateParams},
+        AudioItem,
+    };
+
+    #[test]
+    fn it_emits_numbered_typed_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let events = Arc::new(EventBus::default());
+        let emitted = Arc::new(Mutex::new(vec![]));
+        let emitted_ = Arc::clone(&emitted);
+        events.set_sink(move |event| {
+            emitted_
+                .lock()
+                .unwrap()
+                .push(serde_json::to_value(event).unwrap())
+        });
+        let mut db = Notifying::new(
+            Box::new(FSDatabase::open(dir.path().join("data.json"), dir.path()).unwrap()),
+            events.clone(),
+        );
+
+        db.save_audio_item(AudioItem::new("a".to_owned())).unwrap();
+        db.update_audio_items(UpdateParams {
+            id: "a",
+            label: Some(" Hello there.".to_owned()),
+            is_playing: Some(true),
+            ..Default::default()
+        })
+        .unwrap();
+        db.update_audio_items(UpdateParams {
+            id: "a",
+            tags: Some(vec!["idea".to_owned()]),
+            ..Default::default()
+        })
+        .unwrap();
+        db.remove_item("a").unwrap();
+
+        let emitted: Vec<(u64, String)> = emitted
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                (
+                    e["seq"].as_u64().unwrap(),
+                    e["type"].as_str().unwrap().to_owned(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            emitted,
+            vec![
+                (1, "item_created".to_owned()),
+                (2, "label_updated".to_owned()),
+                (3, "playback_started".to_owned()),
+                (4, "item_updated".to_owned()),
+                (5, "item_removed".to_owned()),
+            ]
+        );
+        assert_eq!(events.snapshot(&db).seq, 5);
+    }
+}
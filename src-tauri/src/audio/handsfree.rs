@@ -0,0 +1,160 @@
+//! Hands-free recording: while it is on the input is monitored, and a take starts when the
+//! level rises above a threshold and ends once it has been quiet for a while. Takes are started
+//! and ended like with the record button, so they are transcribed and saved the same way.
+
+use std::{
+    sync::{
+        atomic::AtomicBool,
+        mpsc::{Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::settings::HandsFree;
+
+use super::{
+    meter::{Level, LEVEL_MS},
+    AudioItem, StreamControlCommand,
+};
+
+/// whether hands-free mode is on
+pub static IS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// how long the level has to stay above the threshold for a take to start, so that a knock
+/// doesn't start one
+const ONSET_MS: u32 = 100;
+/// pre-roll hands-free takes begin with at least, to cover the onset, the level it was detected
+/// in and starting the take, so that they begin where the voice crossed the threshold
+pub const MIN_PRE_ROLL_MS: u32 = ONSET_MS + 2 * LEVEL_MS;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Voice {
+    Started,
+    Stopped,
+}
+
+/// Tells from the levels of the input when someone starts and stops playing or singing.
+pub struct VoiceDetector {
+    settings: HandsFree,
+    is_voiced: bool,
+    /// how long the level has been on the other side of the threshold
+    crossed_ms: u32,
+}
+
+impl VoiceDetector {
+    pub fn new(settings: HandsFree) -> Self {
+        Self {
+            settings,
+            is_voiced: false,
+            crossed_ms: 0,
+        }
+    }
+
+    /// Stops detecting, ending the voice if it was going on.
+    pub fn finish(self) -> Option<Voice> {
+        self.is_voiced.then_some(Voice::Stopped)
+    }
+
+    /// Takes the next level, `level_ms` long.
+    pub fn update(&mut self, level: &Level, level_ms: u32) -> Option<Voice> {
+        let db = 20.0 * level.rms.max(f32::MIN_POSITIVE).log10();
+        let is_loud = db >= self.settings.threshold_db;
+
+        if is_loud == self.is_voiced {
+            self.crossed_ms = 0;
+            return None;
+        }
+
+        self.crossed_ms += level_ms;
+        let wait_ms = if self.is_voiced {
+            self.settings.silence_ms
+        } else {
+            ONSET_MS
+        };
+        if self.crossed_ms < wait_ms {
+            return None;
+        }
+
+        self.is_voiced = is_loud;
+        self.crossed_ms = 0;
+        Some(if is_loud {
+            Voice::Started
+        } else {
+            Voice::Stopped
+        })
+    }
+}
+
+/// Starts and ends takes on the recorder and the transcriber's listener as voice comes and
+/// goes. `take` is the recorder's take in progress, one started by hand is left alone.
+pub fn spawn(
+    voice: Receiver<Voice>,
+    take: Arc<Mutex<Option<AudioItem>>>,
+    recorder: Sender<StreamControlCommand>,
+    listener: Sender<StreamControlCommand>,
+) {
+    thread::spawn(move || {
+        // the id of the take we started
+        let mut ours: Option<String> = None;
+
+        for change in voice {
+            let current = take.lock().unwrap().as_ref().map(|item| item.id.clone());
+
+            match change {
+                Voice::Started if current.is_none() => {
+                    eprintln!("[info] voice detected, starting a take");
+                    let id = cuid2::cuid();
+                    let _ = recorder.send(StreamControlCommand::Play(id.clone()));
+                    let _ = listener.send(StreamControlCommand::Play(id.clone()));
+                    ours = Some(id);
+                }
+                Voice::Started => {}
+                // unless it was already ended by hand
+                Voice::Stopped if current.is_some() && current == ours => {
+                    eprintln!("[info] silence, ending the take");
+                    let _ = recorder.send(StreamControlCommand::Pause(None));
+                    let _ = listener.send(StreamControlCommand::Pause(None));
+                    ours = None;
+                }
+                Voice::Stopped => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Voice, VoiceDetector};
+    use crate::{audio::meter::Level, settings::HandsFree};
+
+    #[test]
+    fn it_waits_for_sustained_voice_and_silence() {
+        let mut detector = VoiceDetector::new(HandsFree {
+            threshold_db: -30.0,
+            silence_ms: 200,
+        });
+        let level = |rms: f32| Level {
+            rms,
+            peak: rms,
+            clipped: false,
+            waveform: vec![],
+        };
+        let mut feed = |rms: f32, times: usize| -> Vec<Voice> {
+            (0..times)
+                .filter_map(|_| detector.update(&level(rms), 50))
+                .collect()
+        };
+
+        // a knock, -14 dB, then quiet at -46 dB
+        assert_eq!(feed(0.2, 1), []);
+        assert_eq!(feed(0.005, 5), []);
+
+        assert_eq!(feed(0.2, 2), [Voice::Started]);
+        // a breath doesn't end the take
+        assert_eq!(feed(0.005, 3), []);
+        assert_eq!(feed(0.2, 10), []);
+        assert_eq!(feed(0.0, 4), [Voice::Stopped]);
+        assert_eq!(feed(0.0, 10), []);
+    }
+}
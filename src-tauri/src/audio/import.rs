@@ -0,0 +1,283 @@
+//! Brings audio files recorded elsewhere into the library, converted to the same 32 bit float
+//! wav files the recorder writes.
+
+use std::{
+    fs,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::mpsc::Sender,
+    time::UNIX_EPOCH,
+};
+
+use anyhow::{bail, Context};
+use rodio::{buffer::SamplesBuffer, source::UniformSourceIterator, Source};
+
+use super::{
+    database::SharedDatabase,
+    duplicates::ContentHasher,
+    metadata,
+    stt::{Transcription, MAX_AUDIO_LEN_SECONDS, WHISPER_CHANNEL_COUNT, WHISPER_SAMPLE_RATE},
+    AudioItem,
+};
+
+/// What gets picked up when walking a directory, files given directly are always tried.
+pub const SUPPORTED_EXTENSIONS: [&str; 4] = ["wav", "flac", "mp3", "ogg"];
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ImportReport {
+    /// ids of the created items
+    pub imported: Vec<String>,
+    pub failed: Vec<ImportFailure>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ImportFailure {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+pub struct Decoded {
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// interleaved
+    pub samples: Vec<f32>,
+}
+
+/// Imports every file in `paths`, walking into directories, and queues each new item for
+/// transcription. A file that can't be imported doesn't stop the others.
+pub fn import(
+    db: &SharedDatabase,
+    transcriber: &Sender<Transcription>,
+    paths: &[PathBuf],
+) -> ImportReport {
+    let mut report = ImportReport::default();
+
+    for path in collect_files(paths, &mut report) {
+        match import_file(db, &path) {
+            Ok(transcription) => {
+                eprintln!("[info] imported {:?} as {}", path, transcription.id);
+                report.imported.push(transcription.id.clone());
+
+                if transcriber.send(transcription).is_err() {
+                    eprintln!("[err] transcriber is gone, imported items won't get labels");
+                }
+            }
+            Err(err) => {
+                eprintln!("[err] failed to import {:?}: {err:#}", path);
+                report.failed.push(ImportFailure {
+                    path,
+                    reason: format!("{err:#}"),
+                });
+            }
+        }
+    }
+
+    report
+}
+
+fn import_file(db: &SharedDatabase, path: &Path) -> anyhow::Result<Transcription> {
+    let decoded = decode(path)?;
+    let mut item = AudioItem::new(cuid2::cuid());
+
+    item.original_filename = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+    if let Some(timestamp) = file_timestamp(path) {
+        item.created_at = Some(timestamp);
+    }
+
+    item.content_hash = Some(write_wav(&item.filepath, &decoded)?);
+
+    let saved = metadata::probe(&mut item).and_then(|()| {
+        db.lock()
+            .unwrap()
+            .save_audio_item(item.clone())
+            .context("failed to save imported audio item")
+    });
+    if let Err(err) = saved {
+        let _ = fs::remove_file(&item.filepath);
+        return Err(err);
+    }
+
+    Ok(Transcription {
+        id: item.id,
+        samples: for_whisper(&decoded),
+    })
+}
+
+/// Decodes any format rodio can, into f32 samples.
+pub fn decode(path: &Path) -> anyhow::Result<Decoded> {
+    let file = fs::File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+    let decoder = rodio::Decoder::new(BufReader::new(file))
+        .with_context(|| format!("failed to decode {:?}", path))?;
+
+    let channels = decoder.channels();
+    let sample_rate = decoder.sample_rate();
+    let samples: Vec<f32> = decoder.convert_samples().collect();
+
+    if samples.is_empty() {
+        bail!("{:?} has no audio", path);
+    }
+
+    Ok(Decoded {
+        channels,
+        sample_rate,
+        samples,
+    })
+}
+
+/// The start of the audio, downmixed and resampled the way whisper wants it.
+pub fn for_whisper(decoded: &Decoded) -> Vec<f32> {
+    let head_len =
+        decoded.sample_rate as usize * decoded.channels as usize * MAX_AUDIO_LEN_SECONDS as usize;
+    let head = &decoded.samples[..head_len.min(decoded.samples.len())];
+
+    UniformSourceIterator::<_, f32>::new(
+        SamplesBuffer::new(decoded.channels, decoded.sample_rate, head),
+        WHISPER_CHANNEL_COUNT,
+        WHISPER_SAMPLE_RATE,
+    )
+    .collect()
+}
+
+/// Returns the content hash of the written audio.
+fn write_wav(path: &Path, decoded: &Decoded) -> anyhow::Result<String> {
+    let spec = hound::WavSpec {
+        channels: decoded.channels,
+        sample_rate: decoded.sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+
+    let write = || -> hound::Result<String> {
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        let mut hasher = ContentHasher::new(spec);
+        for sample in decoded.samples.iter() {
+            writer.write_sample(*sample)?;
+            hasher.update_f32(*sample);
+        }
+        writer.finalize()?;
+        Ok(hasher.finish())
+    };
+
+    write().or_else(|err| {
+        let _ = fs::remove_file(path);
+        Err(err).with_context(|| format!("failed to write {:?}", path))
+    })
+}
+
+/// When the original was last modified, which for a voice memo is close to when it was
+/// recorded, unlike its creation time that copying resets.
+fn file_timestamp(path: &Path) -> Option<u64> {
+    let file = fs::metadata(path).ok()?;
+
+    file.modified()
+        .or_else(|_| file.created())
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_millis() as u64)
+}
+
+fn collect_files(paths: &[PathBuf], report: &mut ImportReport) -> Vec<PathBuf> {
+    let mut files = vec![];
+
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+
+        let mut found = vec![];
+        if let Err(err) = walk(path, &mut found) {
+            report.failed.push(ImportFailure {
+                path: path.clone(),
+                reason: format!("{err:#}"),
+            });
+        }
+        found.sort();
+        files.extend(found);
+    }
+
+    files
+}
+
+fn walk(dir: &Path, found: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let entries = fs::read_dir(dir).with_context(|| format!("failed to read dir {:?}", dir))?;
+
+    for entry in entries {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            walk(&path, found)?;
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| SUPPORTED_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        {
+            found.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{collect_files, decode, for_whisper, ImportReport};
+    use crate::audio::stt::WHISPER_SAMPLE_RATE;
+
+    #[test]
+    fn it_decodes_and_prepares_audio_for_whisper() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("memo.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..44100 * 2 {
+            writer.write_sample((i % 100) as i16 * 100).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let decoded = decode(&path).unwrap();
+
+        assert_eq!(decoded.channels, 2);
+        assert_eq!(decoded.sample_rate, 44100);
+        assert_eq!(decoded.samples.len(), 44100 * 2);
+        assert!(decoded.samples.iter().all(|s| (-1.0..=1.0).contains(s)));
+
+        let whisper = for_whisper(&decoded);
+        assert!(whisper.len().abs_diff(WHISPER_SAMPLE_RATE as usize) <= 1);
+    }
+
+    #[test]
+    fn it_only_picks_up_audio_files_from_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("older")).unwrap();
+        for name in ["b.mp3", "a.WAV", "notes.txt", "older/c.flac"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        let explicit = dir.path().join("notes.txt");
+
+        let files = collect_files(
+            &[dir.path().to_path_buf(), explicit.clone()],
+            &mut ImportReport::default(),
+        );
+
+        assert_eq!(
+            files,
+            vec![
+                dir.path().join("a.WAV"),
+                dir.path().join("b.mp3"),
+                dir.path().join("older/c.flac"),
+                explicit,
+            ]
+        );
+    }
+}
@@ -0,0 +1,455 @@
+//! Finds where the item records and the wav files on disk disagree, and puts them back in line.
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::Sender,
+};
+
+use anyhow::{anyhow, bail, Context};
+
+use super::{
+    app_dir,
+    database::{SharedDatabase, UpdateParams},
+    import, metadata,
+    stt::Transcription,
+    trash::trash_dir,
+    unix_millis_now, AudioItem,
+};
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Issue {
+    /// a wav file in the library that no item refers to
+    OrphanFile { path: PathBuf },
+    /// the item's file is gone or can't be opened
+    MissingFile {
+        id: String,
+        path: PathBuf,
+        reason: String,
+    },
+    CorruptHeader {
+        id: String,
+        path: PathBuf,
+        reason: String,
+    },
+    /// the item is marked missing but its file is back
+    FileFound { id: String },
+    /// the item is marked as playing but the player isn't playing it
+    StalePlaying { id: String },
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Repair {
+    Done { action: String },
+    Failed { reason: String },
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Finding {
+    pub issue: Issue,
+    /// none unless a repair was asked for
+    pub repair: Option<Repair>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct IntegrityReport {
+    pub items_checked: usize,
+    pub findings: Vec<Finding>,
+}
+
+/// Checks every item, trashed ones included, against the wav files in the library and trash
+/// dirs. `playing` is the item the player is playing right now, `recording` the file of the take
+/// being recorded. The database is only locked to take a snapshot.
+pub fn check(
+    db: &SharedDatabase,
+    playing: Option<&str>,
+    recording: Option<&Path>,
+) -> IntegrityReport {
+    let items: Vec<AudioItem> = {
+        let db = db.lock().unwrap();
+        db.items().into_iter().chain(db.trashed_items()).collect()
+    };
+
+    IntegrityReport {
+        items_checked: items.len(),
+        findings: scan(&items, &[app_dir(), trash_dir()], playing, recording)
+            .into_iter()
+            .map(|issue| Finding {
+                issue,
+                repair: None,
+            })
+            .collect(),
+    }
+}
+
+/// Fixes what [`check`] found: adopts orphans as new items (queuing them for transcription),
+/// marks items with missing audio, rewrites truncated headers and resets stale flags.
+pub fn repair(
+    db: &SharedDatabase,
+    transcriber: &Sender<Transcription>,
+    report: &mut IntegrityReport,
+) {
+    for finding in report.findings.iter_mut() {
+        let repaired = match &finding.issue {
+            Issue::OrphanFile { path } => {
+                adopt(db, transcriber, path).map(|id| format!("adopted as audio item {id}"))
+            }
+            Issue::MissingFile { id, .. } => {
+                set_missing(db, id, true).map(|()| "marked as missing".to_owned())
+            }
+            Issue::FileFound { id } => {
+                set_missing(db, id, false).map(|()| "no longer marked as missing".to_owned())
+            }
+            Issue::CorruptHeader { id, path, .. } => rewrite_header(path)
+                .and_then(|()| reprobe(db, id))
+                .map(|()| "rewrote the wav header to match the audio on disk".to_owned()),
+            Issue::StalePlaying { id } => db
+                .lock()
+                .unwrap()
+                .update_audio_items(UpdateParams {
+                    id,
+                    is_playing: Some(false),
+                    ..Default::default()
+                })
+                .map(|_| "reset the playing flag".to_owned()),
+        };
+
+        finding.repair = Some(match repaired {
+            Ok(action) => Repair::Done { action },
+            Err(err) => {
+                eprintln!("[err] failed to repair {:?}: {err:#}", finding.issue);
+                Repair::Failed {
+                    reason: format!("{err:#}"),
+                }
+            }
+        });
+    }
+}
+
+fn scan(
+    items: &[AudioItem],
+    wav_dirs: &[PathBuf],
+    playing: Option<&str>,
+    recording: Option<&Path>,
+) -> Vec<Issue> {
+    let mut issues = vec![];
+
+    for item in items {
+        match inspect(&item.filepath) {
+            Ok(()) if item.is_missing => issues.push(Issue::FileFound {
+                id: item.id.clone(),
+            }),
+            Ok(()) => {}
+            Err(FileProblem::Missing(reason)) => issues.push(Issue::MissingFile {
+                id: item.id.clone(),
+                path: item.filepath.clone(),
+                reason,
+            }),
+            Err(FileProblem::Corrupt(reason)) => issues.push(Issue::CorruptHeader {
+                id: item.id.clone(),
+                path: item.filepath.clone(),
+                reason,
+            }),
+        }
+
+        if item.is_playing && playing != Some(item.id.as_str()) {
+            issues.push(Issue::StalePlaying {
+                id: item.id.clone(),
+            });
+        }
+    }
+
+    let known: HashSet<&Path> = items.iter().map(|item| item.filepath.as_path()).collect();
+    for dir in wav_dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+
+        let mut orphans: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "wav"))
+            .filter(|path| !known.contains(path.as_path()))
+            // not in the database until it is done
+            .filter(|path| Some(path.as_path()) != recording)
+            .collect();
+        orphans.sort();
+
+        issues.extend(orphans.into_iter().map(|path| Issue::OrphanFile { path }));
+    }
+
+    issues
+}
+
+enum FileProblem {
+    Missing(String),
+    Corrupt(String),
+}
+
+fn inspect(path: &Path) -> Result<(), FileProblem> {
+    let mut file = fs::File::open(path).map_err(|err| FileProblem::Missing(err.to_string()))?;
+    let file_len = file
+        .metadata()
+        .map_err(|err| FileProblem::Missing(err.to_string()))?
+        .len();
+
+    let layout = read_layout(&mut file).map_err(|err| FileProblem::Corrupt(format!("{err:#}")))?;
+    let on_disk = file_len.saturating_sub(layout.data_start);
+    if layout.data_len as u64 != on_disk {
+        return Err(FileProblem::Corrupt(format!(
+            "header says {} bytes of audio but the file has {on_disk}",
+            layout.data_len
+        )));
+    }
+
+    file.rewind()
+        .map_err(|err| FileProblem::Missing(err.to_string()))?;
+    hound::WavReader::new(io::BufReader::new(file))
+        .map(|_| ())
+        .map_err(|err| FileProblem::Corrupt(err.to_string()))
+}
+
+/// Where things are in a wav file, as far as its header goes.
+struct Layout {
+    block_align: u16,
+    /// offset of the data chunk's size field
+    data_len_offset: u64,
+    data_start: u64,
+    data_len: u32,
+}
+
+fn read_layout(file: &mut fs::File) -> anyhow::Result<Layout> {
+    let mut riff = [0; 12];
+    file.read_exact(&mut riff).context("file is too short")?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        bail!("not a RIFF/WAVE file");
+    }
+
+    let mut block_align = None;
+    loop {
+        let offset = file.stream_position()?;
+        let mut chunk = [0; 8];
+        file.read_exact(&mut chunk)
+            .context("no data chunk before the end of the file")?;
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+
+        match &chunk[0..4] {
+            b"data" => {
+                return Ok(Layout {
+                    block_align: block_align.ok_or_else(|| anyhow!("no fmt chunk before data"))?,
+                    data_len_offset: offset + 4,
+                    data_start: offset + 8,
+                    data_len: size,
+                });
+            }
+            b"fmt " => {
+                let mut fmt = [0; 16];
+                file.read_exact(&mut fmt)
+                    .context("fmt chunk is too short")?;
+                block_align = Some(u16::from_le_bytes([fmt[12], fmt[13]]).max(1));
+                file.seek(SeekFrom::Start(
+                    offset + 8 + size as u64 + (size & 1) as u64,
+                ))?;
+            }
+            _ => {
+                file.seek(SeekFrom::Start(
+                    offset + 8 + size as u64 + (size & 1) as u64,
+                ))?;
+            }
+        }
+    }
+}
+
+/// Sets the sizes in the header to the whole frames actually on disk, which is what a crash
+/// before the wav writer finalized the file leaves wrong.
+fn rewrite_header(path: &Path) -> anyhow::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("failed to open {:?}", path))?;
+    let layout = read_layout(&mut file)?;
+
+    let on_disk = file.metadata()?.len().saturating_sub(layout.data_start);
+    let data_len = u32::try_from(on_disk - on_disk % layout.block_align as u64)
+        .context("audio is too long for a wav file")?;
+    let riff_len = (layout.data_start - 8) as u32 + data_len;
+
+    file.set_len(layout.data_start + data_len as u64)?;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&riff_len.to_le_bytes())?;
+    file.seek(SeekFrom::Start(layout.data_len_offset))?;
+    file.write_all(&data_len.to_le_bytes())?;
+    file.sync_all()?;
+
+    hound::WavReader::open(path).context("wav is still unreadable")?;
+
+    Ok(())
+}
+
+/// The duration and size change with a rewritten header.
+fn reprobe(db: &SharedDatabase, id: &str) -> anyhow::Result<()> {
+    let mut db = db.lock().unwrap();
+    let mut item = db
+        .get(id)
+        .ok_or_else(|| anyhow!("no audio item with id {id}"))?;
+
+    metadata::probe(&mut item)?;
+    db.save_audio_item(item)
+}
+
+fn set_missing(db: &SharedDatabase, id: &str, is_missing: bool) -> anyhow::Result<()> {
+    db.lock().unwrap().update_audio_items(UpdateParams {
+        id,
+        is_missing: Some(is_missing),
+        ..Default::default()
+    })?;
+
+    Ok(())
+}
+
+/// Creates an item for an orphaned file, keeping its file name as the id when that is a free,
+/// well formed id. Orphans in the trash dir are adopted as trashed items.
+fn adopt(
+    db: &SharedDatabase,
+    transcriber: &Sender<Transcription>,
+    path: &Path,
+) -> anyhow::Result<String> {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()));
+    let is_trashed = path.parent() == Some(trash_dir().as_path());
+
+    let mut db_ = db.lock().unwrap();
+    let id = match stem {
+        Some(stem) if db_.get(stem).is_none() => stem.to_owned(),
+        _ => cuid2::cuid(),
+    };
+
+    let mut item = AudioItem::new(id.clone());
+    item.filepath = path.with_file_name(&id).with_extension("wav");
+    // unknown, probing takes it from the file
+    item.created_at = None;
+    if is_trashed {
+        item.deleted_at = Some(unix_millis_now());
+    }
+
+    if item.filepath != path {
+        fs::rename(path, &item.filepath)
+            .with_context(|| format!("failed to move {:?} to {:?}", path, item.filepath))?;
+    }
+    if let Err(err) = metadata::probe(&mut item) {
+        eprintln!("[warn] adopting {:?} without metadata: {err:#}", path);
+    }
+    db_.save_audio_item(item.clone())?;
+    drop(db_);
+
+    if !is_trashed {
+        match import::decode(&item.filepath) {
+            Ok(decoded) => {
+                let _ = transcriber.send(Transcription {
+                    id: id.clone(),
+                    samples: import::for_whisper(&decoded),
+                });
+            }
+            Err(err) => eprintln!("[warn] not transcribing adopted {id}: {err:#}"),
+        }
+    }
+
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{inspect, rewrite_header, scan, Issue};
+    use crate::audio::AudioItem;
+
+    fn write_wav(path: &std::path::Path, frames: u32) {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for i in 0..frames * 2 {
+            writer.write_sample(i as f32 / 1000.0).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    fn item(dir: &std::path::Path, id: &str) -> AudioItem {
+        let mut item = AudioItem::new(id.to_owned());
+        item.filepath = dir.join(id).with_extension("wav");
+        item
+    }
+
+    #[test]
+    fn it_finds_every_kind_of_issue() {
+        let dir = tempfile::tempdir().unwrap();
+        let fine = item(dir.path(), "fine");
+        write_wav(&fine.filepath, 10);
+        let gone = item(dir.path(), "gone");
+        let mut stale = item(dir.path(), "stale");
+        stale.is_playing = true;
+        write_wav(&stale.filepath, 10);
+        let mut back = item(dir.path(), "back");
+        back.is_missing = true;
+        write_wav(&back.filepath, 10);
+        write_wav(&dir.path().join("orphan.wav"), 10);
+        write_wav(&dir.path().join("recording.wav"), 10);
+
+        let issues = scan(
+            &[fine, gone, stale, back],
+            &[dir.path().to_path_buf()],
+            None,
+            Some(&dir.path().join("recording.wav")),
+        );
+
+        let kinds: Vec<String> = issues
+            .iter()
+            .map(|issue| match issue {
+                Issue::OrphanFile { path } => format!("orphan {}", path.display()),
+                Issue::MissingFile { id, .. } => format!("missing {id}"),
+                Issue::CorruptHeader { id, .. } => format!("corrupt {id}"),
+                Issue::FileFound { id } => format!("found {id}"),
+                Issue::StalePlaying { id } => format!("stale {id}"),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "missing gone".to_owned(),
+                "stale stale".to_owned(),
+                "found back".to_owned(),
+                format!("orphan {}", dir.path().join("orphan.wav").display()),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_rewrites_the_header_of_an_unfinalized_wav() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crashed.wav");
+        write_wav(&path, 100);
+        // what's on disk when the writer never got to finalize: sizes of 0 and half a frame
+        let mut bytes = fs::read(&path).unwrap();
+        let data_len_offset = bytes.windows(4).position(|w| w == b"data").unwrap() + 4;
+        bytes[4..8].copy_from_slice(&0u32.to_le_bytes());
+        bytes[data_len_offset..data_len_offset + 4].copy_from_slice(&0u32.to_le_bytes());
+        bytes.extend([0; 4]);
+        fs::write(&path, bytes).unwrap();
+
+        assert!(inspect(&path).is_err());
+        rewrite_header(&path).unwrap();
+
+        assert!(inspect(&path).is_ok());
+        assert_eq!(hound::WavReader::open(&path).unwrap().duration(), 100);
+    }
+}
@@ -0,0 +1,380 @@
+//! Where the library (the database and the audio files) lives, and moving it elsewhere.
+//!
+//! The root is picked once at startup, `VOECHOAL_LIBRARY` over the `library_dir` setting over
+//! the platform's data dir, and can be switched at runtime. Libraries from before this was
+//! configurable live in `~/voechoal`, which stays in use until it is migrated.
+//!
+//! An open library is locked, a second instance can't open it too and write over its changes.
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+};
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::settings::Settings;
+
+use super::{
+    database::{self, Backend, Database},
+    events::Change,
+    observed, AudioCtrls,
+};
+
+pub const LIBRARY_ENV: &str = "VOECHOAL_LIBRARY";
+const TRASH_DIR: &str = "trash";
+const LOCK_FILE: &str = ".lock";
+/// files that make a directory a library
+const DATABASE_FILES: [&str; 2] = ["library.db", "data.json"];
+
+static ROOT: RwLock<Option<PathBuf>> = RwLock::new(None);
+/// the lock on the active library
+static LOCK: Mutex<Option<LibraryLock>> = Mutex::new(None);
+
+/// Held for as long as the library is open. The lock file holds the pid of the holder.
+pub struct LibraryLock {
+    _file: fs::File,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "the library at {root:?} is already open in another voechoal{}",
+    pid.map(|pid| format!(" (pid {pid})")).unwrap_or_default()
+)]
+pub struct LibraryInUse {
+    pub root: PathBuf,
+    pub pid: Option<u32>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LibraryInfo {
+    pub root: PathBuf,
+    pub default_root: Option<PathBuf>,
+    /// the library still lives in `~/voechoal` and can be migrated
+    pub is_legacy: bool,
+    /// `VOECHOAL_LIBRARY` decides the root at startup, regardless of the settings
+    pub overridden_by_env: bool,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct MigrationReport {
+    pub items_moved: usize,
+    pub collections_moved: usize,
+    /// files of items that were outside of the old library, they stay where they are
+    pub files_left_in_place: Vec<PathBuf>,
+}
+
+/// The root of the active library.
+pub fn root() -> PathBuf {
+    if let Some(root) = ROOT.read().unwrap().as_ref() {
+        return root.clone();
+    }
+
+    // only before setup picked a root
+    default_root().unwrap_or_else(|_| PathBuf::from("voechoal"))
+}
+
+pub fn trash_dir() -> PathBuf {
+    root().join(TRASH_DIR)
+}
+
+/// `$XDG_DATA_HOME/voechoal` or the platform's equivalent.
+pub fn default_root() -> anyhow::Result<PathBuf> {
+    dirs::data_dir()
+        .map(|dir| dir.join("voechoal"))
+        .ok_or_else(|| anyhow!("failed to resolve the data directory"))
+}
+
+/// `~/voechoal`, where libraries used to be.
+pub fn legacy_root() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join("voechoal"))
+}
+
+pub fn info() -> LibraryInfo {
+    let root = root();
+
+    LibraryInfo {
+        is_legacy: legacy_root().is_some_and(|legacy| legacy == root),
+        default_root: default_root().ok(),
+        overridden_by_env: std::env::var_os(LIBRARY_ENV).is_some(),
+        root,
+    }
+}
+
+/// The root to open at startup.
+pub fn resolve(settings: &Settings) -> anyhow::Result<PathBuf> {
+    if let Some(dir) = std::env::var_os(LIBRARY_ENV) {
+        return Ok(dir.into());
+    }
+    if let Some(dir) = settings.library_dir.as_ref() {
+        return Ok(dir.clone());
+    }
+
+    let default = default_root()?;
+    // don't hide an existing library behind a new empty one before it's migrated
+    match legacy_root() {
+        Some(legacy) if !is_library(&default) && is_library(&legacy) => {
+            eprintln!(
+                "[info] using the library in {:?}, it can be migrated",
+                legacy
+            );
+            Ok(legacy)
+        }
+        _ => Ok(default),
+    }
+}
+
+fn is_library(dir: &Path) -> bool {
+    DATABASE_FILES.iter().any(|name| dir.join(name).exists())
+}
+
+/// Creates the library dir and locks it, fails with [`LibraryInUse`] if another process holds
+/// the lock.
+pub fn lock(root: &Path) -> anyhow::Result<LibraryLock> {
+    fs::create_dir_all(root.join(TRASH_DIR))
+        .with_context(|| format!("failed to create library dir {:?}", root))?;
+
+    let path = root.join(LOCK_FILE);
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("failed to open lock file {:?}", path))?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(fs::TryLockError::WouldBlock) => {
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            return Err(LibraryInUse {
+                root: root.to_path_buf(),
+                pid: pid.trim().parse().ok(),
+            }
+            .into());
+        }
+        Err(fs::TryLockError::Error(err)) => {
+            return Err(err).with_context(|| format!("failed to lock {:?}", path));
+        }
+    }
+
+    file.set_len(0)
+        .and_then(|()| file.write_all(std::process::id().to_string().as_bytes()))
+        .with_context(|| format!("failed to write lock file {:?}", path))?;
+
+    Ok(LibraryLock { _file: file })
+}
+
+/// Makes `root` the active root, holding on to its lock and releasing the previous one.
+pub fn set_root(root: &Path, lock: LibraryLock) {
+    *ROOT.write().unwrap() = Some(root.to_path_buf());
+    *LOCK.lock().unwrap() = Some(lock);
+    eprintln!("[info] library is at {:?}", root);
+}
+
+/// Opens the library at `root`, creating it if needed, in place of the active one.
+pub fn switch(ctrls: &AudioCtrls, root: &Path) -> anyhow::Result<()> {
+    let mut db = ctrls.db.lock().unwrap();
+    if self::root() == root {
+        return Ok(());
+    }
+
+    let lock = lock(root)?;
+    let opened = database::open(Backend::from_env(), root)?;
+
+    *db = observed(opened, &ctrls.search, &ctrls.events);
+    set_root(root, lock);
+    ctrls.events.emit(Change::LibrarySwitched);
+
+    Ok(())
+}
+
+/// Moves the active library into `to`, which must not hold a library with items yet, and
+/// switches to it. Audio files are moved and the items' paths rewritten, the old database files
+/// are left behind with a `.migrated` extension.
+pub fn migrate(ctrls: &AudioCtrls, to: &Path) -> anyhow::Result<MigrationReport> {
+    let mut db = ctrls.db.lock().unwrap();
+    let from = root();
+    if from == to {
+        bail!("the library is already at {:?}", to);
+    }
+
+    let lock = lock(to)?;
+    let mut target = database::open(Backend::from_env(), to)?;
+    if !target.items().is_empty() || !target.trashed_items().is_empty() {
+        bail!("there already is a library with items at {:?}", to);
+    }
+
+    let report = move_library(db.as_ref(), &from, target.as_mut(), to)?;
+
+    *db = observed(target, &ctrls.search, &ctrls.events);
+    set_root(to, lock);
+    ctrls.events.emit(Change::LibrarySwitched);
+
+    for name in DATABASE_FILES {
+        let path = from.join(name);
+        if path.exists() {
+            let mut migrated = path.clone().into_os_string();
+            migrated.push(".migrated");
+            if let Err(err) = fs::rename(&path, &migrated) {
+                eprintln!("[warn] failed to set aside {:?}: {err}", path);
+            }
+        }
+    }
+
+    eprintln!(
+        "[info] migrated {} audio items from {:?} to {:?}",
+        report.items_moved, from, to
+    );
+
+    Ok(report)
+}
+
+/// Copies every record of `source` into `target`, moving the audio files under `from` to the
+/// same place under `to`. Undoes the moves if anything fails, `source` is never changed.
+fn move_library(
+    source: &dyn Database,
+    from: &Path,
+    target: &mut dyn Database,
+    to: &Path,
+) -> anyhow::Result<MigrationReport> {
+    let mut report = MigrationReport::default();
+    let mut moved: Vec<(PathBuf, PathBuf)> = vec![];
+    let mut saved: Vec<String> = vec![];
+
+    let mut copy = || -> anyhow::Result<()> {
+        for mut item in source.items().into_iter().chain(source.trashed_items()) {
+            match item.filepath.strip_prefix(from) {
+                Ok(relative) => {
+                    let new_path = to.join(relative);
+                    if item.filepath.exists() {
+                        move_file(&item.filepath, &new_path)?;
+                        moved.push((item.filepath.clone(), new_path.clone()));
+                    }
+                    item.filepath = new_path;
+                }
+                Err(_) => report.files_left_in_place.push(item.filepath.clone()),
+            }
+            item.is_playing = false;
+
+            saved.push(item.id.clone());
+            target.save_audio_item(item)?;
+            report.items_moved += 1;
+        }
+
+        for collection in source.collections() {
+            let created = target.create_collection(collection.name)?;
+            for id in collection.item_ids.iter() {
+                target.add_to_collection(&created.id, id)?;
+            }
+            report.collections_moved += 1;
+        }
+
+        Ok(())
+    };
+
+    if let Err(err) = copy() {
+        for (original, new_path) in moved.iter().rev() {
+            if let Err(err) = move_file(new_path, original) {
+                eprintln!("[err] failed to move {:?} back: {err:#}", new_path);
+            }
+        }
+        for id in saved.iter() {
+            let _ = target.remove_item(id);
+        }
+        for collection in target.collections() {
+            let _ = target.delete_collection(&collection.id);
+        }
+
+        return Err(err.context("failed to migrate the library, nothing was moved"));
+    }
+
+    Ok(report)
+}
+
+/// Falls back to copying when the file can't be renamed, e.g. across file systems.
+fn move_file(from: &Path, to: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Err(err.into()),
+        Err(_) => {
+            fs::copy(from, to).with_context(|| format!("failed to copy {:?} to {:?}", from, to))?;
+            fs::remove_file(from).with_context(|| format!("failed to remove {:?}", from))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{lock, move_library, LibraryInUse};
+    use crate::audio::{
+        database::{Database, FSDatabase},
+        AudioItem,
+    };
+
+    #[test]
+    fn it_moves_audio_and_rewrites_paths() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+        fs::create_dir(from.path().join("trash")).unwrap();
+        let mut source = FSDatabase::open(from.path().join("data.json"), from.path()).unwrap();
+        let mut target = FSDatabase::open(to.path().join("data.json"), to.path()).unwrap();
+
+        let mut kept = AudioItem::new("kept".to_owned());
+        kept.filepath = from.path().join("kept.wav");
+        let mut trashed = AudioItem::new("trashed".to_owned());
+        trashed.filepath = from.path().join("trash/trashed.wav");
+        trashed.deleted_at = Some(1);
+        let mut elsewhere = AudioItem::new("elsewhere".to_owned());
+        elsewhere.filepath = "/somewhere/else.wav".into();
+        for item in [&kept, &trashed] {
+            fs::write(&item.filepath, b"RIFF").unwrap();
+        }
+        for item in [kept, trashed, elsewhere] {
+            source.save_audio_item(item).unwrap();
+        }
+        let collection = source.create_collection("Summer song".to_owned()).unwrap();
+        source.add_to_collection(&collection.id, "kept").unwrap();
+
+        let report = move_library(&source, from.path(), &mut target, to.path()).unwrap();
+
+        assert_eq!(report.items_moved, 3);
+        assert_eq!(
+            report.files_left_in_place,
+            vec![std::path::PathBuf::from("/somewhere/else.wav")]
+        );
+        assert_eq!(
+            target.get("kept").unwrap().filepath,
+            to.path().join("kept.wav")
+        );
+        assert_eq!(
+            target.get("trashed").unwrap().filepath,
+            to.path().join("trash/trashed.wav")
+        );
+        assert!(to.path().join("trash/trashed.wav").exists());
+        assert!(!from.path().join("kept.wav").exists());
+        assert_eq!(target.collections()[0].item_ids, vec!["kept".to_owned()]);
+    }
+
+    #[test]
+    fn it_refuses_a_library_locked_by_someone_else() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let held = lock(dir.path()).unwrap();
+        let err = lock(dir.path()).err().unwrap();
+        let in_use = err.downcast_ref::<LibraryInUse>().unwrap();
+        assert_eq!(in_use.pid, Some(std::process::id()));
+
+        drop(held);
+        assert!(lock(dir.path()).is_ok());
+    }
+}
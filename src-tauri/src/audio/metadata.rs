@@ -0,0 +1,93 @@
+use std::{fs, thread, time::UNIX_EPOCH};
+
+use anyhow::Context;
+
+use super::{database::SharedDatabase, duplicates, AudioItem};
+
+/// Fills in the item's duration, format and size from its wav file.
+pub fn probe(item: &mut AudioItem) -> anyhow::Result<()> {
+    let reader = hound::WavReader::open(&item.filepath)
+        .with_context(|| format!("failed to read wav header of {:?}", item.filepath))?;
+    let spec = reader.spec();
+    let file = fs::metadata(&item.filepath)
+        .with_context(|| format!("failed to stat {:?}", item.filepath))?;
+
+    item.duration_ms = Some(reader.duration() as u64 * 1000 / spec.sample_rate.max(1) as u64);
+    item.sample_rate = Some(spec.sample_rate);
+    item.channels = Some(spec.channels);
+    item.sample_format = Some(sample_format_name(spec));
+    item.file_size = Some(file.len());
+
+    if item.created_at.is_none() {
+        item.created_at = file
+            .created()
+            .or_else(|_| file.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64);
+    }
+
+    Ok(())
+}
+
+/// e.g. `f32` or `i16`
+pub fn sample_format_name(spec: hound::WavSpec) -> String {
+    let kind = match spec.sample_format {
+        hound::SampleFormat::Float => 'f',
+        hound::SampleFormat::Int => 'i',
+    };
+
+    format!("{kind}{}", spec.bits_per_sample)
+}
+
+/// Probes and hashes, off the calling thread, the wav files of items recorded before their
+/// metadata or content hash was captured.
+pub fn spawn_backfill(db: SharedDatabase) {
+    thread::spawn(move || {
+        let items: Vec<AudioItem> = {
+            let db = db.lock().unwrap();
+            db.items()
+                .into_iter()
+                .chain(db.trashed_items())
+                .filter(|item| item.duration_ms.is_none() || item.content_hash.is_none())
+                .collect()
+        };
+
+        if items.is_empty() {
+            return;
+        }
+        eprintln!("[info] backfilling metadata of {} audio items", items.len());
+
+        for mut item in items {
+            if item.duration_ms.is_none() {
+                if let Err(err) = probe(&mut item) {
+                    eprintln!("[warn] {err:#}");
+                    continue;
+                }
+            }
+            if item.content_hash.is_none() {
+                match duplicates::content_hash(&item.filepath) {
+                    Ok(hash) => item.content_hash = Some(hash),
+                    Err(err) => eprintln!("[warn] {err:#}"),
+                }
+            }
+
+            let mut db = db.lock().unwrap();
+            // the item may have changed while we were probing, only take the probed fields over
+            let Some(mut current) = db.get(&item.id) else {
+                continue;
+            };
+            current.created_at = current.created_at.or(item.created_at);
+            current.duration_ms = item.duration_ms;
+            current.sample_rate = item.sample_rate;
+            current.channels = item.channels;
+            current.sample_format = item.sample_format;
+            current.file_size = item.file_size;
+            current.content_hash = current.content_hash.or(item.content_hash);
+
+            if let Err(err) = db.save_audio_item(current) {
+                eprintln!("[err] failed to save backfilled metadata: {err:#}");
+            }
+        }
+    });
+}
@@ -0,0 +1,144 @@
+//! Input levels while recording or listening hands-free, so it shows that the mic is picking
+//! something up. They are measured by the capture writer as it takes samples off the ring
+//! buffer, the input stream's callback does no more than before.
+
+use std::sync::{Arc, RwLock};
+
+/// Name of the tauri event the levels are emitted as.
+pub const LEVEL_EVENT: &str = "input-level";
+/// levels per second of audio
+const LEVELS_PER_SECOND: usize = 20;
+/// how much audio a level is measured over
+pub const LEVEL_MS: u32 = 1000 / LEVELS_PER_SECOND as u32;
+/// points of the waveform preview per level
+const WAVEFORM_POINTS: usize = 16;
+/// samples this close to full scale count as clipped
+const CLIP_LEVEL: f32 = 0.999;
+
+pub type SharedLevels = Arc<Levels>;
+
+type Sink = Box<dyn Fn(&Level) + Send + Sync>;
+
+/// The input over the last 1/20 of a second, as written to the take. Levels go from 0 to 1,
+/// full scale.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Level {
+    pub rms: f32,
+    pub peak: f32,
+    /// some samples reached full scale
+    pub clipped: bool,
+    /// peaks of consecutive stretches of the input, oldest first
+    pub waveform: Vec<f32>,
+}
+
+/// Hands levels to the sink, which is set once the app can emit events.
+#[derive(Default)]
+pub struct Levels {
+    sink: RwLock<Option<Sink>>,
+}
+
+impl Levels {
+    pub fn set_sink(&self, sink: impl Fn(&Level) + Send + Sync + 'static) {
+        *self.sink.write().unwrap() = Some(Box::new(sink));
+    }
+
+    pub fn publish(&self, level: &Level) {
+        if let Some(sink) = self.sink.read().unwrap().as_ref() {
+            sink(level);
+        }
+    }
+}
+
+/// Sums up samples into a [`Level`] per interval of audio, so they come at a steady rate
+/// however the input delivers them.
+pub struct Meter {
+    /// samples per level, of all channels
+    interval: usize,
+    count: usize,
+    sum_of_squares: f64,
+    peak: f32,
+    clipped: bool,
+    waveform: Vec<f32>,
+}
+
+impl Meter {
+    pub fn new(spec: hound::WavSpec) -> Self {
+        let samples_per_second = spec.sample_rate as usize * spec.channels as usize;
+
+        Self {
+            interval: (samples_per_second / LEVELS_PER_SECOND).max(1),
+            count: 0,
+            sum_of_squares: 0.0,
+            peak: 0.0,
+            clipped: false,
+            waveform: vec![0.0; WAVEFORM_POINTS],
+        }
+    }
+
+    /// Measures `samples`, calling `publish` with the level of every interval they complete.
+    pub fn feed(&mut self, samples: &[f32], mut publish: impl FnMut(Level)) {
+        for sample in samples {
+            let magnitude = sample.abs();
+            let point = self.count * WAVEFORM_POINTS / self.interval;
+
+            self.sum_of_squares += (*sample as f64).powi(2);
+            self.peak = self.peak.max(magnitude);
+            self.clipped |= magnitude >= CLIP_LEVEL;
+            self.waveform[point] = self.waveform[point].max(magnitude);
+            self.count += 1;
+
+            if self.count == self.interval {
+                publish(self.take_level());
+            }
+        }
+    }
+
+    fn take_level(&mut self) -> Level {
+        let level = Level {
+            rms: (self.sum_of_squares / self.count as f64).sqrt() as f32,
+            peak: self.peak,
+            clipped: self.clipped,
+            waveform: std::mem::replace(&mut self.waveform, vec![0.0; WAVEFORM_POINTS]),
+        };
+
+        self.count = 0;
+        self.sum_of_squares = 0.0;
+        self.peak = 0.0;
+        self.clipped = false;
+
+        level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Meter, WAVEFORM_POINTS};
+
+    #[test]
+    fn it_publishes_a_level_per_interval() {
+        // 20 samples per level
+        let mut meter = Meter::new(hound::WavSpec {
+            channels: 2,
+            sample_rate: 200,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        });
+        let mut levels = vec![];
+
+        meter.feed(&[0.5; 30], |level| levels.push(level));
+        assert_eq!(levels.len(), 1);
+        meter.feed(&[-0.5; 5], |level| levels.push(level));
+        meter.feed(&[1.0; 5], |level| levels.push(level));
+        assert_eq!(levels.len(), 2);
+
+        assert_eq!(levels[0].rms, 0.5);
+        assert_eq!(levels[0].peak, 0.5);
+        assert!(!levels[0].clipped);
+        assert_eq!(levels[0].waveform, vec![0.5; WAVEFORM_POINTS]);
+
+        assert!((levels[1].rms - 0.6614).abs() < 1e-4);
+        assert_eq!(levels[1].peak, 1.0);
+        assert!(levels[1].clipped);
+        assert_eq!(levels[1].waveform.last(), Some(&1.0));
+    }
+}
@@ -1,43 +1,116 @@
 use std::{
     path::PathBuf,
-    sync::{Arc, Mutex},
+    sync::{mpsc, Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use crate::background::procedure::BackgroundProcedure;
+use crate::{
+    background::procedure::BackgroundProcedure,
+    settings::{self, SharedSettings},
+};
 
 pub enum StreamControlCommand {
     /// play audio item by id
     Play(String),
+    /// stops playing, or ends the take and saves it
     Pause(Option<String>),
+    /// pauses the take, it goes on with what's recorded after `Resume`
+    Suspend,
+    Resume,
+    /// ends the take without saving or transcribing it
+    Cancel,
+    /// record from the input device with this name from now on, the default one if none
+    UseInput(Option<String>),
+    /// keep the input running between takes, listening for voice to start them, none stops
+    Monitor(Option<settings::HandsFree>),
+    /// begin takes with this many milliseconds of input from before they were started
+    PreRoll(u32),
 }
 
+/// The take being recorded, none between takes.
+pub type SharedTake = Arc<Mutex<Option<AudioItem>>>;
+
 pub struct AudioCtrls {
     pub player: BackgroundProcedure<Option<String>, StreamControlCommand>,
-    pub ecouter: BackgroundProcedure<Vec<f32>, StreamControlCommand>,
+    pub ecouter: BackgroundProcedure<Option<AudioItem>, StreamControlCommand>,
     pub sttlistener: BackgroundProcedure<(), StreamControlCommand>,
-    pub db: Arc<Mutex<database::FSDatabase>>,
+    pub transcriber: BackgroundProcedure<(), stt::Transcription>,
+    pub sweeper: BackgroundProcedure<Option<retention::SweepReport>, ()>,
+    pub db: database::SharedDatabase,
+    pub search: search::SharedSearchIndex,
+    pub events: events::SharedEventBus,
+    pub levels: meter::SharedLevels,
 }
 
-pub fn setup() -> anyhow::Result<AudioCtrls> {
-    let db = Arc::new(Mutex::new(database::FSDatabase::new()));
+pub fn setup(settings: SharedSettings) -> anyhow::Result<AudioCtrls> {
+    let root = library::resolve(&settings.lock().unwrap())?;
+    let input = settings.lock().unwrap().input_device.clone();
+    let pre_roll_ms = settings.lock().unwrap().pre_roll_ms;
+    let lock = library::lock(&root)?;
+    library::set_root(&root, lock);
+
+    let search = search::SharedSearchIndex::default();
+    let events = events::SharedEventBus::default();
+    let db: database::SharedDatabase = Arc::new(Mutex::new(observed(
+        database::open(database::Backend::from_env(), &root)?,
+        &search,
+        &events,
+    )));
+    trash::spawn_purger(db.clone(), settings.clone());
+    let sweeper = retention::setup(db.clone(), settings);
+    metadata::spawn_backfill(db.clone());
+    database::spawn_reloader(db.clone());
     let host = cpal::default_host();
-    let sttlistener = stt::listener::setup(&host, db.clone());
-    let ectrl = ecouter::setup(&host, db.clone())?;
+    let levels = meter::SharedLevels::default();
+    let (voice, voice_changes) = mpsc::channel();
+    let ectrl = ecouter::setup(
+        input.clone(),
+        pre_roll_ms,
+        db.clone(),
+        levels.clone(),
+        voice,
+        events.clone(),
+    )?;
+    let transcriber = stt::transcriber::setup(db.clone(), ectrl.state.clone(), events.clone());
+    let sttlistener = stt::listener::setup(input, transcriber.tx.clone());
+    handsfree::spawn(
+        voice_changes,
+        ectrl.state.clone(),
+        ectrl.tx.clone(),
+        sttlistener.tx.clone(),
+    );
     let pctrl = player::setup(&host, db.clone())?;
 
     return Ok(AudioCtrls {
         player: pctrl,
         ecouter: ectrl,
         sttlistener,
+        transcriber,
+        sweeper,
         db,
+        search,
+        events,
+        levels,
     });
 }
 
+/// Wraps a freshly opened database in everything that follows the changes made to it.
+fn observed(
+    db: Box<dyn database::Database>,
+    search: &search::SharedSearchIndex,
+    events: &events::SharedEventBus,
+) -> Box<dyn database::Database> {
+    Box::new(events::Notifying::new(
+        Box::new(search::Indexed::new(db, search.clone())),
+        events.clone(),
+    ))
+}
+
 pub mod player {
     use std::{
         fs,
         io::BufReader,
-        sync::{mpsc::channel, Arc, Mutex},
+        sync::{mpsc::channel, Arc},
     };
 
     use anyhow::{anyhow, Context};
@@ -45,11 +118,11 @@ pub mod player {
 
     use crate::{audio::database::UpdateParams, background::procedure::BackgroundProcedure};
 
-    use super::{app_dir, database::FSDatabase, StreamControlCommand};
+    use super::{database::SharedDatabase, StreamControlCommand};
 
     pub fn setup(
         host: &cpal::Host,
-        db: Arc<Mutex<FSDatabase>>,
+        db: SharedDatabase,
     ) -> anyhow::Result<BackgroundProcedure<Option<String>, StreamControlCommand>> {
         let speakers = host
             .default_output_device()
@@ -72,14 +145,20 @@ pub mod player {
                 let (tx_id, rx_id) = channel::<String>();
 
                 let db_clone = Arc::clone(&db);
+                // the id of the item that is playing, if any
+                let playing = Arc::clone(&arg.state);
                 let _ = std::thread::spawn(move || loop {
                     let id = rx_id.recv().unwrap();
-                    let wavfilepath = app_dir().join(&id).with_extension("wav");
+                    // where the item's audio is, it needn't be in the library dir under its id
+                    let wavfilepath = db_clone.lock().unwrap().get(&id).map(|item| item.filepath);
 
                     eprintln!("[info] tyring to open the wav file for playing item: {id}");
-                    let file = match fs::File::open(&wavfilepath)
-                        .context(anyhow!("failed to open file: {:?}", wavfilepath))
-                    {
+                    let file = match wavfilepath
+                        .ok_or_else(|| anyhow!("no audio item with id {id}"))
+                        .and_then(|path| {
+                            fs::File::open(&path)
+                                .with_context(|| format!("failed to open file: {:?}", path))
+                        }) {
                         Ok(f) => f,
                         Err(err) => {
                             db_clone
@@ -88,8 +167,7 @@ pub mod player {
                                 .update_audio_items(UpdateParams {
                                     id: &id,
                                     is_playing: Some(false),
-                                    filepath: None,
-                                    label: None,
+                                    ..Default::default()
                                 })
                                 .expect("failed to mark audio item as paused");
                             eprintln!("[err] {err:#}");
@@ -107,14 +185,20 @@ pub mod player {
 
                     eprintln!("[info] audio item {} is done playing", id);
 
+                    {
+                        let mut playing = playing.lock().unwrap();
+                        if playing.as_ref() == Some(&id) {
+                            *playing = None;
+                        }
+                    }
+
                     db_clone
                         .lock()
                         .unwrap()
                         .update_audio_items(UpdateParams {
                             id: &id,
                             is_playing: Some(false),
-                            filepath: None,
-                            label: None,
+                            ..Default::default()
                         })
                         .expect("failed to mark audio item as paused");
                 });
@@ -143,8 +227,7 @@ pub mod player {
                                     .update_audio_items(UpdateParams {
                                         id: prev_id,
                                         is_playing: Some(false),
-                                        filepath: None,
-                                        label: None,
+                                        ..Default::default()
                                     })
                                     .expect("failed to mark audio item as paused");
                             }
@@ -154,11 +237,11 @@ pub mod player {
                                 .update_audio_items(UpdateParams {
                                     id: &id,
                                     is_playing: Some(true),
-                                    filepath: None,
-                                    label: None,
+                                    ..Default::default()
                                 })
                                 .expect("failed to mark audio item as playing");
 
+                            *arg.state.lock().unwrap() = Some(id.clone());
                             tx_id
                                 .send(id.clone())
                                 .expect("failed to send audio item id");
@@ -170,6 +253,7 @@ pub mod player {
                             eprintln!("[info] requested to pause item: {:?}", id);
 
                             sink.pause();
+                            *arg.state.lock().unwrap() = None;
 
                             if let Some(id) = id {
                                 db.lock()
@@ -177,12 +261,19 @@ pub mod player {
                                     .update_audio_items(UpdateParams {
                                         id: &id,
                                         is_playing: Some(false),
-                                        filepath: None,
-                                        label: None,
+                                        ..Default::default()
                                     })
                                     .expect("failed to mark audio item as paused");
                             }
                         }
+                        Ok(
+                            StreamControlCommand::Suspend
+                            | StreamControlCommand::Resume
+                            | StreamControlCommand::Cancel
+                            | StreamControlCommand::UseInput(_)
+                            | StreamControlCommand::Monitor(_)
+                            | StreamControlCommand::PreRoll(_),
+                        ) => {}
                         Err(err) => {
                             eprintln!("[error] recieve err on channel: {}", err);
                             return;
@@ -196,87 +287,146 @@ pub mod player {
 }
 
 pub mod ecouter {
-    use std::sync::{Arc, Mutex};
+    use anyhow::Context;
+    use cpal::traits::{DeviceTrait, StreamTrait};
 
-    use cpal::traits::StreamTrait;
+    use std::sync::{mpsc::Sender, RwLock};
 
     use crate::{
-        audio::{audio_stream_err_fn, database::wav_spec_from},
+        audio::{
+            capture::Capture,
+            database::wav_spec_from,
+            devices,
+            events::{Change, EventBus, SharedEventBus},
+            handsfree::{self, Voice},
+            metadata,
+            meter::SharedLevels,
+            AudioItem,
+        },
         background::procedure::BackgroundProcedure,
+        ringbuf,
+        settings::HandsFree,
     };
 
-    use super::{database::FSDatabase, StreamControlCommand};
-
-    pub fn setup(
-        host: &cpal::Host,
-        db: Arc<Mutex<FSDatabase>>,
-    ) -> anyhow::Result<BackgroundProcedure<Vec<f32>, StreamControlCommand>> {
-        use cpal::traits::{DeviceTrait, HostTrait};
-
-        let mic = host
-            .default_input_device()
-            .expect("no input device available");
+    use super::{database::SharedDatabase, SharedTake, StreamControlCommand};
+
+    /// how much audio the ring buffer between the input stream and the writer holds
+    const RING_SECONDS: usize = 5;
+
+    /// What the recorder is doing, whoever started the take.
+    pub static RECORD_STATE: RwLock<RecordState> = RwLock::new(RecordState::Idle);
+
+    #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+    #[serde(tag = "status", rename_all = "snake_case")]
+    pub enum RecordState {
+        Idle,
+        Recording {
+            id: String,
+        },
+        /// the take is paused, but not over
+        Suspended {
+            id: String,
+        },
+    }
 
-        let supported_config = mic
-            .default_input_config()
-            .expect("no supported input config?!");
-        let config = supported_config.into();
+    /// The stream of the device being recorded from.
+    struct Input {
+        stream: cpal::Stream,
+        /// what the device delivers, before it is converted to `f32`
+        format: cpal::SampleFormat,
+        capture: Capture,
+    }
 
-        eprintln!("[debug] input config: {:?}", config);
+    /// Records from the input device named `input`, the default one if none, beginning takes
+    /// with `pre_roll_ms` of what came before. The state is the take being recorded, it is only
+    /// saved to the database once it is done. Voice coming and going is sent to `voice` while
+    /// monitoring, takes starting and stopping to `events`.
+    pub fn setup(
+        input: Option<String>,
+        pre_roll_ms: u32,
+        db: SharedDatabase,
+        levels: SharedLevels,
+        voice: Sender<Voice>,
+        events: SharedEventBus,
+    ) -> anyhow::Result<BackgroundProcedure<Option<AudioItem>, StreamControlCommand>> {
+        let job_handle = BackgroundProcedure::<Option<AudioItem>, StreamControlCommand>::setup(
+            None,
+            move |arg| {
+                let open = |name: Option<&str>| -> Option<Input> {
+                    match open_input(name, levels.clone(), voice.clone()) {
+                        Ok(input) => Some(input),
+                        Err(err) => {
+                            eprintln!("[err] {err:#}");
+                            None
+                        }
+                    }
+                };
 
-        let job_handle =
-            BackgroundProcedure::<Vec<f32>, StreamControlCommand>::setup(vec![], move |arg| {
-                let audio_buffer = arg.state;
-                let audio_buffer_ref = Arc::clone(&audio_buffer);
-
-                let stream = mic
-                    .build_input_stream(
-                        &config,
-                        move |data: &[f32], _| {
-                            // eprintln!("[info]: data len {:?}", &data.len());
-                            audio_buffer_ref
-                                .lock()
-                                .expect("failed to lock on audio_buffer")
-                                .extend(data);
-                        },
-                        audio_stream_err_fn,
-                        None,
-                    )
-                    .expect("failed to build_input_stream");
-
-                stream.pause().expect("failed to pause the input stream");
-
-                let pause = |stream: &cpal::Stream,
-                             config: &cpal::StreamConfig,
-                             new_audio_item_id: String| {
-                    stream.pause().expect("failed to pause the input stream");
+                // the take stays in progress until it is saved, only locks the database to save it
+                let finish = |input: &Input, keep_running: bool| {
+                    let Some(mut audio_item) = arg.state.lock().unwrap().clone() else {
+                        return;
+                    };
+                    if !keep_running {
+                        if let Err(err) = input.stream.pause() {
+                            eprintln!("[err] failed to pause the input stream: {err}");
+                        }
+                    }
                     eprintln!("[info] done listening");
 
-                    let audio_item = db.lock().unwrap().get_or_create(&new_audio_item_id);
+                    match input.capture.stop() {
+                        Ok(hash) => audio_item.content_hash = Some(hash),
+                        Err(err) => {
+                            eprintln!(
+                                "[err] failed to record audio item {}: {err:#}",
+                                audio_item.id
+                            );
+                            arg.state.lock().unwrap().take();
+                            set_state(&events, RecordState::Idle);
+                            return;
+                        }
+                    }
+                    audio_item.input_format = Some(input.format.to_string());
 
-                    eprintln!("[info] write wav file for new audio item");
-                    {
-                        db.lock().unwrap().write_to_wav(
-                            &audio_item,
-                            &audio_buffer.lock().expect("failed to lock on audio_buffer"),
-                            wav_spec_from(config),
-                        );
+                    if let Err(err) = metadata::probe(&mut audio_item) {
+                        eprintln!("[err] {err:#}");
                     }
 
                     eprintln!("[info] saving audio item");
-                    db.lock()
-                        .unwrap()
-                        .save_audio_item(audio_item)
-                        .expect("failed to to save new audio item");
+                    if let Err(err) = save_take(&db, &arg.state, audio_item) {
+                        eprintln!("[err] failed to save new audio item: {err:#}");
+                    }
+                    set_state(&events, RecordState::Idle);
+                };
 
-                    audio_buffer
-                        .lock()
-                        .expect("failed to lock on audio_buffer")
-                        .clear();
-                    eprintln!("[trace] cleared audio_buffer");
+                // between takes the input keeps running to listen for voice or keep the pre-roll
+                let keep_running = |input: &Input, should_run: bool, is_recording: bool| {
+                    if should_run {
+                        if let Err(err) = input.stream.play() {
+                            eprintln!("[err] failed to play the input stream: {err}");
+                        }
+                    } else if !is_recording {
+                        if let Err(err) = input.stream.pause() {
+                            eprintln!("[err] failed to pause the input stream: {err}");
+                        }
+                    }
+                };
+
+                // hands-free takes begin where the voice crossed the threshold, before it was
+                // detected
+                let lead = |pre_roll_ms: u32, monitoring: &Option<HandsFree>| match monitoring {
+                    Some(_) => pre_roll_ms.max(handsfree::MIN_PRE_ROLL_MS),
+                    None => pre_roll_ms,
                 };
 
-                let mut current_new_audio_item_id = None;
+                let mut monitoring: Option<HandsFree> = None;
+                let mut pre_roll_ms = pre_roll_ms;
+                let mut is_suspended = false;
+                let mut input_stream = open(input.as_deref());
+                if let Some(input) = input_stream.as_ref() {
+                    input.capture.pre_roll(pre_roll_ms);
+                    keep_running(input, pre_roll_ms > 0, false);
+                }
                 loop {
                     let ctrl = arg
                         .rx
@@ -285,164 +435,228 @@ pub mod ecouter {
 
                     match ctrl {
                         StreamControlCommand::Play(id) => {
+                            let Some(input) = input_stream.as_ref() else {
+                                eprintln!("[err] no input device to record from");
+                                continue;
+                            };
+                            let mut take = arg.state.lock().unwrap();
+                            if take.is_some() {
+                                continue;
+                            }
+
+                            let mut audio_item = AudioItem::new(id);
+                            match input.capture.start(&audio_item.filepath) {
+                                Ok(ms) => audio_item.pre_roll_ms = Some(ms),
+                                Err(err) => {
+                                    eprintln!("[err] {err:#}");
+                                    continue;
+                                }
+                            }
                             eprintln!("[info] listening...");
-                            current_new_audio_item_id = Some(id);
-                            stream.play().expect("failed to play the input stream");
+                            let id = audio_item.id.clone();
+                            *take = Some(audio_item);
+                            set_state(&events, RecordState::Recording { id });
+                            if let Err(err) = input.stream.play() {
+                                eprintln!("[err] failed to play the input stream: {err}");
+                            }
                         }
                         StreamControlCommand::Pause(_) => {
-                            if let Some(id) = current_new_audio_item_id.clone() {
-                                pause(&stream, &config, id);
+                            if let Some(input) = input_stream.as_ref() {
+                                finish(input, monitoring.is_some() || pre_roll_ms > 0);
+                            }
+                            is_suspended = false;
+                        }
+                        StreamControlCommand::Suspend => {
+                            let Some(input) = input_stream.as_ref() else {
+                                continue;
+                            };
+                            let Some(id) = recording_id(&arg.state) else {
+                                continue;
+                            };
+                            if is_suspended {
+                                continue;
+                            }
+
+                            // everything up to the pause is kept
+                            keep_running(input, monitoring.is_some() || pre_roll_ms > 0, false);
+                            input.capture.suspend();
+                            is_suspended = true;
+                            set_state(&events, RecordState::Suspended { id });
+                            eprintln!("[info] take suspended");
+                        }
+                        StreamControlCommand::Resume => {
+                            let Some(input) = input_stream.as_ref() else {
+                                continue;
+                            };
+                            let Some(id) = recording_id(&arg.state) else {
+                                continue;
+                            };
+                            if !is_suspended {
+                                continue;
+                            }
+
+                            input.capture.resume();
+                            is_suspended = false;
+                            keep_running(input, true, true);
+                            set_state(&events, RecordState::Recording { id });
+                            eprintln!("[info] take resumed");
+                        }
+                        StreamControlCommand::Cancel => {
+                            let take = arg.state.lock().unwrap().take();
+                            is_suspended = false;
+                            let (Some(audio_item), Some(input)) = (take, input_stream.as_ref())
+                            else {
+                                continue;
+                            };
+
+                            keep_running(input, monitoring.is_some() || pre_roll_ms > 0, false);
+                            match input.capture.cancel() {
+                                Ok(()) => eprintln!("[info] discarded take {}", audio_item.id),
+                                Err(err) => eprintln!(
+                                    "[err] failed to discard take {}: {err:#}",
+                                    audio_item.id
+                                ),
+                            }
+                            set_state(&events, RecordState::Idle);
+                        }
+                        StreamControlCommand::UseInput(name) => {
+                            // a take in progress ends with the device it was recorded on
+                            if let Some(input) = input_stream.as_ref() {
+                                finish(input, monitoring.is_some() || pre_roll_ms > 0);
+                            }
+                            is_suspended = false;
+                            // let go of the old device before opening, it may be the same one
+                            drop(input_stream.take());
+                            input_stream = open(name.as_deref());
+                            if let Some(input) = input_stream.as_ref() {
+                                input.capture.detect(monitoring.clone());
+                                input.capture.pre_roll(lead(pre_roll_ms, &monitoring));
+                                keep_running(input, monitoring.is_some() || pre_roll_ms > 0, false);
+                            }
+                        }
+                        StreamControlCommand::Monitor(settings) => {
+                            monitoring = settings;
+                            if let Some(input) = input_stream.as_ref() {
+                                input.capture.detect(monitoring.clone());
+                                input.capture.pre_roll(lead(pre_roll_ms, &monitoring));
+                                keep_running(
+                                    input,
+                                    monitoring.is_some() || pre_roll_ms > 0,
+                                    arg.state.lock().unwrap().is_some(),
+                                );
+                            }
+                        }
+                        StreamControlCommand::PreRoll(ms) => {
+                            pre_roll_ms = ms;
+                            if let Some(input) = input_stream.as_ref() {
+                                input.capture.pre_roll(lead(pre_roll_ms, &monitoring));
+                                keep_running(
+                                    input,
+                                    monitoring.is_some() || pre_roll_ms > 0,
+                                    arg.state.lock().unwrap().is_some(),
+                                );
                             }
                         }
                     };
                 }
-            });
+            },
+        );
 
         Ok(job_handle)
     }
-}
-
-mod database {
-    use std::{
-        collections::BTreeMap,
-        fs,
-        path::{Path, PathBuf},
-    };
-
-    use anyhow::Context;
-    use serde_json::json;
-
-    use crate::audio::app_dir;
-
-    use super::{audio_items_data_file, AudioItem};
-
-    #[derive(serde::Deserialize, serde::Serialize)]
-    pub struct Data {
-        items: BTreeMap<String, AudioItem>,
-    }
-
-    pub struct FSDatabase {
-        wav_dir: PathBuf,
-        datafile: PathBuf,
-        items: BTreeMap<String, AudioItem>,
-    }
 
-    pub struct UpdateParams<'i> {
-        pub id: &'i str,
-        pub filepath: Option<&'i Path>,
-        pub is_playing: Option<bool>,
-        pub label: Option<String>,
+    /// Set before it is emitted, so that a snapshot is never behind the events it includes.
+    fn set_state(events: &EventBus, state: RecordState) {
+        *RECORD_STATE.write().unwrap() = state.clone();
+        events.emit(Change::RecordStateChanged { state });
     }
 
-    pub fn wav_spec_from(config: &cpal::StreamConfig) -> hound::WavSpec {
-        hound::WavSpec {
-            channels: config.channels,
-            sample_rate: config.sample_rate.0,
-            bits_per_sample: 32,
-            sample_format: hound::SampleFormat::Float,
-        }
+    fn recording_id(take: &SharedTake) -> Option<String> {
+        take.lock().unwrap().as_ref().map(|item| item.id.clone())
     }
 
-    impl FSDatabase {
-        pub fn new() -> Self {
-            Self {
-                wav_dir: app_dir(),
-                datafile: audio_items_data_file(),
-                items: Self::load_all().unwrap().items,
-            }
-        }
-
-        pub fn get_or_create(&self, id: &str) -> AudioItem {
-            self.items
-                .get(id)
-                .cloned()
-                .unwrap_or_else(|| AudioItem::new(id.to_owned()))
-        }
-
-        pub fn items(&self) -> Vec<AudioItem> {
-            self.items.values().cloned().collect()
-        }
-
-        pub fn remove_item(&mut self, id: String) {
-            self.items.remove(&id);
+    /// Saves `recorded` with the transcript the take in progress got meanwhile, ending the take.
+    /// Under the database lock, so that a transcript finds the take either here or saved.
+    pub(crate) fn save_take(
+        db: &SharedDatabase,
+        take: &SharedTake,
+        mut recorded: AudioItem,
+    ) -> anyhow::Result<()> {
+        let mut db = db.lock().unwrap();
+        let in_progress = take.lock().unwrap().take();
+        if let Some(in_progress) = in_progress.filter(|item| item.id == recorded.id) {
+            recorded.label = in_progress.label;
         }
 
-        pub fn write_to_wav(&mut self, item: &AudioItem, buffer: &[f32], spec: hound::WavSpec) {
-            eprintln!("[info] writing wav with specs: {:?}", spec);
-            let wav_filepath = self.wav_dir.join(&item.id).with_extension("wav");
-            let mut writer =
-                hound::WavWriter::create(wav_filepath, spec).expect("failed to create wav writer");
-
-            for sample in buffer.iter() {
-                writer
-                    .write_sample(*sample * 2.0)
-                    .expect("failed to write sample");
-            }
-        }
-
-        pub fn load_all() -> anyhow::Result<Data> {
-            let Ok(data) = fs::read_to_string(audio_items_data_file())
-                .context("failed to read from audio items data file")
-            else {
-                return Ok(Data {
-                    items: BTreeMap::new(),
-                });
-            };
-
-            let data: Data = serde_json::from_str(&data)
-                .context("failed to parse audio items data file json")?;
-
-            return Ok(data);
-        }
-
-        pub fn save_audio_item(&mut self, item: AudioItem) -> anyhow::Result<()> {
-            self.items.insert(item.id.clone(), item);
-
-            self.save_all()
-                .context("failed to save new audio item in data file")?;
-
-            Ok(())
-        }
-
-        fn save_all(&self) -> anyhow::Result<()> {
-            let data = json!({
-                "items": self.items
-            });
-            let json_string =
-                serde_json::to_string(&data).context("failed to Serialize audio item")?;
-
-            fs::write(&self.datafile, json_string)?;
+        db.save_audio_item(recorded)
+    }
 
-            Ok(())
-        }
+    fn open_input(
+        name: Option<&str>,
+        levels: SharedLevels,
+        voice: Sender<Voice>,
+    ) -> anyhow::Result<Input> {
+        let mic = devices::input_device(name)?;
+        let supported_config = mic
+            .default_input_config()
+            .context("no supported input config")?;
+        let format = supported_config.sample_format();
+        let config: cpal::StreamConfig = supported_config.into();
 
-        pub fn update_audio_items(&mut self, params: UpdateParams) -> anyhow::Result<bool> {
-            if let Some(item) = self.items.get_mut(params.id) {
-                item.is_playing = params.is_playing.unwrap_or(item.is_playing);
-                item.filepath = params
-                    .filepath
-                    .map(|p| p.to_path_buf())
-                    .unwrap_or(item.filepath.clone());
-                if let Some(e) = params.label {
-                    item.label = Some(e);
-                }
+        eprintln!("[debug] input config: {:?} in {format}", config);
 
-                self.save_all()?;
-                return Ok(true);
-            };
+        let ring_len = config.sample_rate.0 as usize * config.channels as usize * RING_SECONDS;
+        let (mut samples, recorded) = ringbuf::channel(ring_len);
+        let stream = devices::build_input_stream(&mic, &config, format, move |data| {
+            samples.push(data);
+        })?;
 
-            Ok(false)
-        }
+        Ok(Input {
+            capture: Capture::spawn(recorded, wav_spec_from(&config), levels, voice),
+            stream,
+            format,
+        })
     }
 }
 
-mod stt {
+pub mod archive;
+pub mod capture;
+pub mod database;
+pub mod devices;
+pub mod duplicates;
+pub mod events;
+pub mod handsfree;
+pub mod import;
+pub mod integrity;
+pub mod library;
+pub mod metadata;
+pub mod meter;
+pub mod query;
+pub mod ratings;
+pub mod retention;
+pub mod search;
+pub mod tags;
+pub mod titles;
+pub mod trash;
+
+pub mod stt {
     use std::sync::atomic::AtomicBool;
 
     use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
 
     pub static IS_TRANSCRIBING: AtomicBool = AtomicBool::new(false);
 
+    pub const WHISPER_SAMPLE_RATE: u32 = 16000;
+    pub const WHISPER_CHANNEL_COUNT: u16 = 1; // mono because whisper wants it
+    pub const MAX_AUDIO_LEN_SECONDS: u32 = 5;
+
+    /// Audio to transcribe into the label of the item `id`, in whisper's sample rate and
+    /// channel count.
+    pub struct Transcription {
+        pub id: String,
+        pub samples: Vec<f32>,
+    }
+
     pub struct Transcribe {
         ctx: WhisperContext,
     }
@@ -499,160 +713,340 @@ mod stt {
         }
     }
 
+    pub mod transcriber {
+        use crate::{
+            audio::{
+                database::{SharedDatabase, UpdateParams},
+                events::{Change, SharedEventBus},
+                SharedTake,
+            },
+            background::procedure::BackgroundProcedure,
+        };
+
+        use super::Transcription;
+
+        /// Transcribes queued audio one at a time, labelling the items with the transcripts.
+        /// `take` is the recorder's take in progress, which is labelled before it is saved.
+        /// Transcripts of takes that were discarded are dropped.
+        pub fn setup(
+            db: SharedDatabase,
+            take: SharedTake,
+            events: SharedEventBus,
+        ) -> BackgroundProcedure<(), Transcription> {
+            BackgroundProcedure::<_, Transcription>::setup((), move |arg| {
+                let tt = super::Transcribe::new("/home/gnarus/d/caldi/models/ggml-base.en.bin");
+                let prompt = r#"[system]
+                                Transcribe the first 24 words in the song that the user is singing.
+                                [user]"#;
+
+                loop {
+                    let Ok(Transcription { id, samples }) = arg.rx.recv() else {
+                        return;
+                    };
+                    if samples.is_empty() {
+                        eprintln!("[debug] ignoring empty buffer");
+                        continue;
+                    }
+                    if !is_wanted(&db, &take, &id) {
+                        eprintln!("[debug] not transcribing {id}, it was discarded");
+                        continue;
+                    }
+                    eprintln!("[info] started transcribing");
+                    events.emit(Change::TranscriptionStarted { id: id.clone() });
+                    let transcript = tt.transcribe(&samples, prompt);
+
+                    eprintln!("[info] stopped transcribing");
+
+                    match label(&db, &take, &id, transcript) {
+                        Ok(true) => eprintln!("[info] labelled audio item {id}"),
+                        Ok(false) => eprintln!("[debug] dropping the transcript of discarded {id}"),
+                        Err(err) => eprintln!("[err] failed to label audio item {id}: {err:#}"),
+                    }
+                    events.emit(Change::TranscriptionFinished { id });
+                }
+            })
+        }
+
+        fn is_wanted(db: &SharedDatabase, take: &SharedTake, id: &str) -> bool {
+            db.lock().unwrap().get(id).is_some()
+                || take
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .is_some_and(|item| item.id == id)
+        }
+
+        /// Labels the item `id` with `transcript`, or the take in progress if it is still being
+        /// recorded. Returns false if there is neither, the take was discarded.
+        pub(crate) fn label(
+            db: &SharedDatabase,
+            take: &SharedTake,
+            id: &str,
+            transcript: String,
+        ) -> anyhow::Result<bool> {
+            // the recorder saves its take under the database lock, it's in one place or the other
+            let mut db = db.lock().unwrap();
+            let updated = db.update_audio_items(UpdateParams {
+                id,
+                label: Some(transcript.clone()),
+                ..Default::default()
+            })?;
+            if updated {
+                return Ok(true);
+            }
+
+            match take.lock().unwrap().as_mut().filter(|item| item.id == id) {
+                Some(item) => {
+                    item.label = Some(transcript);
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use std::sync::{Arc, Mutex};
+
+            use super::{is_wanted, label};
+            use crate::audio::{
+                database::{SharedDatabase, SqliteDatabase},
+                ecouter::save_take,
+                AudioItem, SharedTake,
+            };
+
+            fn recording(id: &str) -> (tempfile::TempDir, SharedDatabase, SharedTake) {
+                let dir = tempfile::tempdir().unwrap();
+                let db = SqliteDatabase::open(&dir.path().join("library.db")).unwrap();
+                let take = Some(AudioItem::new(id.to_owned()));
+
+                (
+                    dir,
+                    Arc::new(Mutex::new(Box::new(db))),
+                    Arc::new(Mutex::new(take)),
+                )
+            }
+
+            #[test]
+            fn it_keeps_the_transcript_of_a_take_longer_than_the_listener_buffer() {
+                let (_dir, db, take) = recording("a");
+
+                // the listener's buffer filled up before the take ended
+                assert!(label(&db, &take, "a", " Hello there.".to_owned()).unwrap());
+                save_take(&db, &take, AudioItem::new("a".to_owned())).unwrap();
+
+                let item = db.lock().unwrap().get("a").unwrap();
+                assert_eq!(item.label.as_deref(), Some(" Hello there."));
+                assert!(take.lock().unwrap().is_none());
+            }
+
+            #[test]
+            fn it_drops_the_transcript_of_a_cancelled_take() {
+                let (_dir, db, take) = recording("a");
+
+                // queued when the listener's buffer filled, then the take was cancelled
+                take.lock().unwrap().take();
+
+                assert!(!is_wanted(&db, &take, "a"));
+                assert!(!label(&db, &take, "a", " Hello there.".to_owned()).unwrap());
+                assert!(db.lock().unwrap().get("a").is_none());
+            }
+        }
+    }
+
     pub mod listener {
         use core::f32;
         use std::{
-            ops::Deref,
-            sync::{Arc, Mutex},
+            mem,
+            sync::{mpsc::Sender, Arc, Mutex},
         };
 
-        use cpal::traits::{HostTrait, StreamTrait};
+        use anyhow::Context;
+        use cpal::traits::StreamTrait;
         use rodio::DeviceTrait;
 
         use crate::{
-            audio::{self, audio_stream_err_fn, database::FSDatabase, StreamControlCommand},
+            audio::{devices, handsfree, StreamControlCommand},
             background::procedure::BackgroundProcedure,
-            sharedref::SharedMutRef,
         };
 
-        const WHISPER_SAMPLE_RATE: u32 = 16000;
-        const MAX_AUDIO_LEN_SECONDS: u32 = 5;
-        const WHISPER_CHANNEL_COUNT: u16 = 1; // mono because whisper wants it
+        use super::{
+            Transcription, MAX_AUDIO_LEN_SECONDS, WHISPER_CHANNEL_COUNT, WHISPER_SAMPLE_RATE,
+        };
 
-        pub fn setup(
-            host: &cpal::Host,
-            db: Arc<Mutex<FSDatabase>>,
-        ) -> BackgroundProcedure<(), StreamControlCommand> {
-            let mic = host
-                .default_input_device()
-                .expect("failed to get default input device");
+        const BUFFER_SIZE: u32 = WHISPER_SAMPLE_RATE * MAX_AUDIO_LEN_SECONDS;
 
-            let job = BackgroundProcedure::<_, StreamControlCommand>::setup((), move |arg| {
-                let buffer_size = WHISPER_SAMPLE_RATE * MAX_AUDIO_LEN_SECONDS;
-                let config: cpal::StreamConfig = cpal::StreamConfig {
-                    channels: WHISPER_CHANNEL_COUNT,
-                    sample_rate: cpal::SampleRate(WHISPER_SAMPLE_RATE),
-                    buffer_size: cpal::BufferSize::Fixed(buffer_size),
-                };
+        /// while hands-free, the samples kept between takes to begin the next one with
+        const HANDS_FREE_LEAD: usize =
+            (handsfree::MIN_PRE_ROLL_MS * WHISPER_SAMPLE_RATE / 1000) as usize;
 
-                struct Buffer {
-                    cap: u32,
-                    inner: Vec<f32>,
-                }
+        struct Buffer {
+            cap: u32,
+            inner: Vec<f32>,
+            /// a take is being listened to, otherwise only the lead is kept
+            is_listening: bool,
+            /// how many of the latest samples are kept between takes
+            lead: usize,
+        }
 
-                impl Buffer {
-                    fn new(cap: u32) -> Self {
-                        Self {
-                            inner: Vec::with_capacity(cap as usize),
-                            cap,
-                        }
-                    }
+        impl Buffer {
+            fn new(cap: u32) -> Self {
+                Self {
+                    inner: Vec::with_capacity(cap as usize),
+                    cap,
+                    is_listening: false,
+                    lead: 0,
+                }
+            }
 
-                    fn is_full(&self) -> bool {
-                        self.inner.len() >= self.cap as usize
-                    }
+            fn is_full(&self) -> bool {
+                self.inner.len() >= self.cap as usize
+            }
 
-                    fn extends(&mut self, data: &[f32]) {
+            fn extends(&mut self, data: &[f32]) {
+                if self.is_listening {
+                    if !self.is_full() {
                         self.inner.extend(data);
                     }
+                    return;
                 }
 
-                let db_ = Arc::clone(&db);
-
-                let buffer = Arc::new(Mutex::new(Buffer::new(buffer_size)));
-                let buffer_clone1 = Arc::clone(&buffer);
-                let audio_item_id = SharedMutRef::<Option<String>>::new(None);
-                let audio_item_id_ref = audio_item_id.new_ref();
-                let stream = mic
-                    .build_input_stream(
-                        &config,
-                        move |data: &[f32], _| {
-                            eprintln!("[info] data len: {}", data.len());
-                            if !buffer_clone1.lock().unwrap().is_full() {
-                                buffer_clone1.lock().unwrap().extends(data)
-                            }
-                        },
-                        audio_stream_err_fn,
-                        None,
-                    )
-                    .expect("failed to build input stream to listen for stt");
-
-                stream.pause().expect("failed to pause stream");
-
-                let job = BackgroundProcedure::<_, Vec<f32>>::setup((), move |arg| {
-                    let tt = super::Transcribe::new("/home/gnarus/d/caldi/models/ggml-base.en.bin");
-                    let prompt = r#"[system]
-                                    Transcribe the first 24 words in the song that the user is singing.
-                                    [user]"#;
-
-                    loop {
-                        let buffer = arg.rx.recv().expect("failed to recieve from channel");
-                        if buffer.is_empty() {
-                            eprintln!("[debug] ignoring empty buffer");
-                            continue;
+                self.inner.extend(data);
+                let excess = self.inner.len().saturating_sub(self.lead);
+                self.inner.drain(..excess);
+            }
+
+            /// Keeps `lead` samples between takes from now on, none of those kept so far.
+            fn set_lead(&mut self, lead: usize) {
+                self.lead = lead;
+                if !self.is_listening {
+                    self.inner.clear();
+                }
+            }
+
+            /// Starts a take, beginning with the lead.
+            fn listen(&mut self) {
+                self.is_listening = true;
+            }
+
+            /// Ends the take, returning what was listened to.
+            fn finish(&mut self) -> Vec<f32> {
+                self.is_listening = false;
+                mem::take(&mut self.inner)
+            }
+        }
+
+        /// Listens on the input device named `input`, the default one if none.
+        pub fn setup(
+            input: Option<String>,
+            transcriber: Sender<Transcription>,
+        ) -> BackgroundProcedure<(), StreamControlCommand> {
+            let job = BackgroundProcedure::<_, StreamControlCommand>::setup((), move |arg| {
+                let buffer = Arc::new(Mutex::new(Buffer::new(BUFFER_SIZE)));
+
+                let open = |name: Option<&str>| match open_input(name, Arc::clone(&buffer)) {
+                    Ok(stream) => Some(stream),
+                    Err(err) => {
+                        eprintln!("[err] stt can't listen: {err:#}");
+                        None
+                    }
+                };
+
+                // between takes the stream only runs while hands-free, to keep the lead
+                let stand_by = |stream: &Option<cpal::Stream>, is_monitoring: bool| {
+                    let Some(stream) = stream.as_ref() else {
+                        return;
+                    };
+                    if is_monitoring {
+                        if let Err(err) = stream.play() {
+                            eprintln!("[err] failed to play stream: {err}");
                         }
-                        eprintln!("[info] started transcribing");
-                        let transcript = tt.transcribe(&buffer, prompt);
-
-                        eprintln!("[info] stopped transcribing");
-                        let id = audio_item_id_ref
-                            .deref()
-                            .lock()
-                            .unwrap()
-                            .clone()
-                            .expect("audio item should be set if we just transcribed");
-
-                        eprintln!("[info] upserting an audio item");
-                        let update_succeeded = db_
-                            .lock()
-                            .unwrap()
-                            .update_audio_items(crate::audio::database::UpdateParams {
-                                id: &id,
-                                filepath: None,
-                                is_playing: None,
-                                label: Some(transcript.clone()),
-                            })
-                            .expect("failed to update an audio item");
-
-                        if !update_succeeded {
-                            db_.lock()
-                                .unwrap()
-                                .save_audio_item(audio::AudioItem::new_with_label(id, transcript))
-                                .expect("failed to upsert an audio item");
+                    } else if let Err(err) = stream.pause() {
+                        eprintln!("[err] failed to pause stream: {err}");
+                    }
+                };
+
+                let transcribe =
+                    |stream: &Option<cpal::Stream>, id: &Option<String>, is_monitoring: bool| {
+                        eprintln!("[info] stt is done listening");
+                        let samples = buffer.lock().unwrap().finish();
+                        stand_by(stream, is_monitoring);
+
+                        let Some(id) = id.clone() else {
+                            return;
                         };
 
-                        eprintln!("[info] upserted an audio item");
-                    }
-                });
+                        transcriber
+                            .send(Transcription { id, samples })
+                            .expect("failed to queue transcription");
+                    };
 
-                let mut is_done_transcribing = false;
+                let mut stream = open(input.as_deref());
+                let mut audio_item_id = None;
+                let mut is_done_transcribing = true;
+                let mut is_monitoring = false;
                 loop {
                     let command = arg.rx.try_recv();
 
                     match command {
                         Ok(StreamControlCommand::Play(id)) => {
+                            let Some(listening) = stream.as_ref() else {
+                                continue;
+                            };
                             eprintln!("[info] stt is listening...");
-                            *audio_item_id.deref().lock().unwrap() = Some(id);
-                            stream.play().expect("failed to play stream");
+                            audio_item_id = Some(id);
+                            buffer.lock().unwrap().listen();
+                            if let Err(err) = listening.play() {
+                                eprintln!("[err] failed to play stream: {err}");
+                            }
                             is_done_transcribing = false;
                         }
                         Ok(StreamControlCommand::Pause(_)) => {
-                            eprintln!("[info] stt is done listening");
-                            stream.pause().expect("failed to pause stream");
-
-                            job.trigger(buffer.lock().unwrap().inner.clone());
-                            buffer.lock().unwrap().inner.clear();
-
+                            transcribe(&stream, &audio_item_id, is_monitoring);
+                            is_done_transcribing = true;
+                        }
+                        Ok(StreamControlCommand::Suspend) if !is_done_transcribing => {
+                            if let Some(Err(err)) = stream.as_ref().map(|stream| stream.pause()) {
+                                eprintln!("[err] failed to pause stream: {err}");
+                            }
+                        }
+                        Ok(StreamControlCommand::Resume) if !is_done_transcribing => {
+                            if let Some(Err(err)) = stream.as_ref().map(|stream| stream.play()) {
+                                eprintln!("[err] failed to play stream: {err}");
+                            }
+                        }
+                        Ok(StreamControlCommand::Suspend | StreamControlCommand::Resume) => {}
+                        Ok(StreamControlCommand::Cancel) => {
+                            buffer.lock().unwrap().finish();
+                            stand_by(&stream, is_monitoring);
+                            audio_item_id = None;
                             is_done_transcribing = true;
                         }
+                        Ok(StreamControlCommand::UseInput(name)) => {
+                            if !is_done_transcribing {
+                                transcribe(&stream, &audio_item_id, is_monitoring);
+                                is_done_transcribing = true;
+                            }
+                            // let go of the old device before opening, it may be the same one
+                            drop(stream.take());
+                            stream = open(name.as_deref());
+                            stand_by(&stream, is_monitoring);
+                        }
+                        Ok(StreamControlCommand::Monitor(settings)) => {
+                            is_monitoring = settings.is_some();
+                            buffer.lock().unwrap().set_lead(if is_monitoring {
+                                HANDS_FREE_LEAD
+                            } else {
+                                0
+                            });
+                            if is_done_transcribing {
+                                stand_by(&stream, is_monitoring);
+                            }
+                        }
+                        Ok(StreamControlCommand::PreRoll(_)) => {}
                         Err(std::sync::mpsc::TryRecvError::Empty) => {
                             if buffer.lock().unwrap().is_full() && !is_done_transcribing {
-                                eprintln!("[info] stt is done listening");
-                                stream.pause().expect("failed to pause stream");
-
-                                job.trigger(buffer.lock().unwrap().inner.clone());
-                                buffer.lock().unwrap().inner.clear();
-
+                                transcribe(&stream, &audio_item_id, is_monitoring);
                                 is_done_transcribing = true;
                             }
                         }
@@ -663,16 +1057,102 @@ mod stt {
 
             return job;
         }
+
+        fn open_input(
+            name: Option<&str>,
+            buffer: Arc<Mutex<Buffer>>,
+        ) -> anyhow::Result<cpal::Stream> {
+            let mic = devices::input_device(name)?;
+            let format = mic
+                .default_input_config()
+                .context("no supported input config")?
+                .sample_format();
+            let config: cpal::StreamConfig = cpal::StreamConfig {
+                channels: WHISPER_CHANNEL_COUNT,
+                sample_rate: cpal::SampleRate(WHISPER_SAMPLE_RATE),
+                buffer_size: cpal::BufferSize::Fixed(BUFFER_SIZE),
+            };
+
+            devices::build_input_stream(&mic, &config, format, move |data| {
+                eprintln!("[info] data len: {}", data.len());
+                buffer.lock().unwrap().extends(data)
+            })
+            .context("failed to build input stream to listen for stt")
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use super::Buffer;
+
+            #[test]
+            fn it_begins_takes_with_the_lead() {
+                let mut buffer = Buffer::new(8);
+                buffer.set_lead(3);
+
+                buffer.extends(&[1.0, 2.0, 3.0, 4.0]);
+                buffer.extends(&[5.0]);
+                buffer.listen();
+                buffer.extends(&[6.0; 6]);
+                assert!(buffer.is_full());
+                assert_eq!(
+                    buffer.finish(),
+                    [3.0, 4.0, 5.0, 6.0, 6.0, 6.0, 6.0, 6.0, 6.0]
+                );
+
+                buffer.extends(&[7.0]);
+                buffer.set_lead(0);
+                buffer.listen();
+                assert!(buffer.finish().is_empty());
+            }
+        }
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct AudioItem {
     pub id: String,
     pub label: Option<String>,
     pub filepath: PathBuf,
-    #[serde(default)]
     pub is_playing: bool,
+    /// when the item was moved to the trash, in milliseconds since the unix epoch
+    pub deleted_at: Option<u64>,
+    /// in milliseconds since the unix epoch
+    pub created_at: Option<u64>,
+    pub duration_ms: Option<u64>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    /// sample format of the wav file, e.g. `f32`
+    pub sample_format: Option<String>,
+    /// size of the wav file in bytes
+    pub file_size: Option<u64>,
+    pub tags: Vec<String>,
+    /// name of the file the item was imported from, none for recordings
+    pub original_filename: Option<String>,
+    /// set by the integrity check when the item's audio is gone or unreadable
+    pub is_missing: bool,
+    /// sha-256 of the audio's format and samples, equal for identical recordings
+    pub content_hash: Option<String>,
+    /// set by the user, shown instead of the transcript in `label`
+    pub title: Option<String>,
+    /// every title the item was given, oldest first, the last one is the current title
+    pub title_history: Vec<TitleEdit>,
+    pub is_favourite: bool,
+    /// 1 to 5 stars
+    pub rating: Option<u8>,
+    /// sample format the input device recorded in before it was converted to `sample_format`,
+    /// e.g. `i16`, none for imports
+    pub input_format: Option<String>,
+    /// how much of the start of the take is from before recording was started, none for
+    /// imports and takes recorded before pre-roll
+    pub pre_roll_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TitleEdit {
+    /// none if the title was cleared
+    pub title: Option<String>,
+    /// in milliseconds since the unix epoch
+    pub edited_at: u64,
 }
 
 impl AudioItem {
@@ -682,6 +1162,23 @@ impl AudioItem {
             id,
             label: None,
             is_playing: false,
+            deleted_at: None,
+            created_at: Some(unix_millis_now()),
+            duration_ms: None,
+            sample_rate: None,
+            channels: None,
+            sample_format: None,
+            file_size: None,
+            tags: vec![],
+            original_filename: None,
+            is_missing: false,
+            content_hash: None,
+            title: None,
+            title_history: vec![],
+            is_favourite: false,
+            rating: None,
+            input_format: None,
+            pre_roll_ms: None,
         }
     }
 
@@ -693,6 +1190,10 @@ impl AudioItem {
 }
 
 pub mod polling {
+    use std::cmp::Reverse;
+
+    use anyhow::anyhow;
+
     use super::AudioItem;
 
     #[derive(serde::Serialize, Debug)]
@@ -701,13 +1202,88 @@ pub mod polling {
         audio_items: Vec<AudioItem>,
     }
 
+    /// Narrows the polled items down to those with `tag`, in `collection` (by id), favourites
+    /// and those rated at least `min_rating`.
+    #[derive(serde::Deserialize, Debug, Default)]
+    #[serde(default)]
+    pub struct RecordingsFilter {
+        pub tag: Option<String>,
+        pub collection: Option<String>,
+        pub favourites_only: bool,
+        pub min_rating: Option<u8>,
+        /// oldest first, or in the collection's order, if none
+        pub sort: Option<RecordingsSort>,
+    }
+
+    #[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum RecordingsSort {
+        Oldest,
+        Newest,
+        /// most stars first, unrated last
+        Rating,
+        FavouritesFirst,
+        Longest,
+    }
+
+    impl RecordingsSort {
+        /// Stable, ties stay in the order they came in.
+        pub fn sort(self, items: &mut [AudioItem]) {
+            match self {
+                Self::Oldest => items.sort_by_key(|item| item.created_at.unwrap_or(u64::MAX)),
+                Self::Newest => items.sort_by_key(|item| Reverse(item.created_at)),
+                Self::Rating => {
+                    items.sort_by_key(|item| (Reverse(item.rating), Reverse(item.created_at)))
+                }
+                Self::FavouritesFirst => {
+                    items.sort_by_key(|item| (!item.is_favourite, Reverse(item.created_at)))
+                }
+                Self::Longest => items.sort_by_key(|item| Reverse(item.duration_ms)),
+            }
+        }
+    }
+
     impl RecordingsPoll {
-        pub fn poll(db: &super::database::FSDatabase) -> anyhow::Result<Self> {
+        pub fn poll(
+            db: &dyn super::database::Database,
+            filter: &RecordingsFilter,
+        ) -> anyhow::Result<Self> {
             let is_transcribing =
                 super::stt::IS_TRANSCRIBING.load(std::sync::atomic::Ordering::Relaxed);
 
+            let mut audio_items = db.items();
+
+            if let Some(tag) = filter.tag.as_ref() {
+                audio_items.retain(|item| item.tags.contains(tag));
+            }
+            if filter.favourites_only {
+                audio_items.retain(|item| item.is_favourite);
+            }
+            if let Some(min_rating) = filter.min_rating {
+                audio_items.retain(|item| item.rating.is_some_and(|r| r >= min_rating));
+            }
+
+            if let Some(collection_id) = filter.collection.as_ref() {
+                let collection = db
+                    .collections()
+                    .into_iter()
+                    .find(|c| &c.id == collection_id)
+                    .ok_or_else(|| anyhow!("no collection with id {collection_id}"))?;
+
+                // in the collection's order
+                audio_items = collection
+                    .item_ids
+                    .iter()
+                    .filter_map(|id| audio_items.iter().find(|item| &item.id == id).cloned())
+                    .collect();
+            }
+
+            if let Some(sort) = filter.sort {
+                sort.sort(&mut audio_items);
+            }
+
             Ok(Self {
-                audio_items: db.items(),
+                audio_items,
                 is_transcribing,
             })
         }
@@ -715,18 +1291,14 @@ pub mod polling {
 }
 
 fn app_dir() -> PathBuf {
-    let home_dir = std::env::var("HOME").expect("failed to resolve $HOME variable");
-    let save_dir = std::path::Path::new(&home_dir).join("voechoal");
-
-    if !save_dir.is_dir() {
-        std::fs::create_dir(&save_dir).expect("failed to create save dir");
-    }
-
-    return save_dir;
+    library::root()
 }
 
-fn audio_items_data_file() -> PathBuf {
-    app_dir().join("data").with_extension("json")
+fn unix_millis_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the unix epoch")
+        .as_millis() as u64
 }
 
 fn audio_stream_err_fn(err: cpal::StreamError) {
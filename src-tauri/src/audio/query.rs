@@ -0,0 +1,351 @@
+//! One page at a time of the items matching a filter, for libraries too big to poll whole.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+
+use super::{database::Database, polling::RecordingsSort, search::SearchIndex, AudioItem};
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct RecordingsQuery {
+    /// matched against titles and transcripts like `search_recordings`
+    pub text: Option<String>,
+    /// items must have all of them
+    pub tags: Vec<String>,
+    /// collection id
+    pub collection: Option<String>,
+    /// in milliseconds since the unix epoch, inclusive
+    pub created_after: Option<u64>,
+    /// in milliseconds since the unix epoch, exclusive
+    pub created_before: Option<u64>,
+    pub min_duration_ms: Option<u64>,
+    pub max_duration_ms: Option<u64>,
+    pub transcription: Option<TranscriptionStatus>,
+    pub favourites_only: bool,
+    pub min_rating: Option<u8>,
+    /// best match first when there is `text`, oldest first otherwise, if none
+    pub sort: Option<RecordingsSort>,
+    /// `next_cursor` of the previous page, none for the first one
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptionStatus {
+    /// not transcribed yet
+    Pending,
+    Transcribed,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RecordingsPage {
+    pub items: Vec<AudioItem>,
+    /// of all pages
+    pub total: usize,
+    /// none on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// Takes the search index for the `text` filter, lock it after the database. Backends that can
+/// filter and sort by themselves page through their items, the rest are filtered in memory.
+pub fn query(
+    db: &dyn Database,
+    search: &SearchIndex,
+    query: &RecordingsQuery,
+) -> anyhow::Result<RecordingsPage> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let hits: Option<Vec<String>> = query
+        .text
+        .as_deref()
+        .filter(|text| !text.trim().is_empty())
+        .map(|text| {
+            search
+                .search(text, usize::MAX)
+                .into_iter()
+                .map(|hit| hit.id)
+                .collect()
+        });
+
+    if let Some(page) = db.query_page(query, hits.as_deref(), limit) {
+        return page;
+    }
+
+    let mut items = db.items();
+    if let Some(collection_id) = query.collection.as_ref() {
+        let collection = db
+            .collections()
+            .into_iter()
+            .find(|c| &c.id == collection_id)
+            .ok_or_else(|| anyhow!("no collection with id {collection_id}"))?;
+        items.retain(|item| collection.item_ids.contains(&item.id));
+    }
+    items.retain(|item| matches(item, query));
+
+    if let Some(hits) = hits {
+        let ranks: HashMap<String, usize> = hits
+            .into_iter()
+            .enumerate()
+            .map(|(rank, id)| (id, rank))
+            .collect();
+
+        items.retain(|item| ranks.contains_key(&item.id));
+        items.sort_by_key(|item| ranks[&item.id]);
+    }
+    if let Some(sort) = query.sort {
+        sort.sort(&mut items);
+    }
+
+    let start = match query.cursor.as_deref() {
+        Some(cursor) => resume_at(&items, cursor)?,
+        None => 0,
+    };
+    let end = (start + limit).min(items.len());
+    let next_cursor = (end < items.len()).then(|| cursor(end, &items[end - 1]));
+
+    Ok(RecordingsPage {
+        total: items.len(),
+        items: items[start..end].to_vec(),
+        next_cursor,
+    })
+}
+
+fn matches(item: &AudioItem, query: &RecordingsQuery) -> bool {
+    let in_range = |value: Option<u64>, min: Option<u64>, max: Option<u64>, max_inclusive| {
+        if min.is_none() && max.is_none() {
+            return true;
+        }
+        let Some(value) = value else {
+            return false;
+        };
+        min.is_none_or(|min| value >= min)
+            && max.is_none_or(|max| {
+                if max_inclusive {
+                    value <= max
+                } else {
+                    value < max
+                }
+            })
+    };
+
+    query.tags.iter().all(|tag| item.tags.contains(tag))
+        && in_range(
+            item.created_at,
+            query.created_after,
+            query.created_before,
+            false,
+        )
+        && in_range(
+            item.duration_ms,
+            query.min_duration_ms,
+            query.max_duration_ms,
+            true,
+        )
+        && match query.transcription {
+            Some(TranscriptionStatus::Pending) => item.label.is_none(),
+            Some(TranscriptionStatus::Transcribed) => item.label.is_some(),
+            None => true,
+        }
+        && (!query.favourites_only || item.is_favourite)
+        && query
+            .min_rating
+            .is_none_or(|min| item.rating.is_some_and(|r| r >= min))
+}
+
+/// The number of items before the page and the last item on the previous page. The item
+/// decides where the next page starts, so that items coming and going in between don't shift
+/// the pages, the number is only used when that item is gone.
+pub(crate) fn cursor(offset: usize, last: &AudioItem) -> String {
+    format!("{offset}:{}", last.id)
+}
+
+/// The offset and the item id of a [`cursor`].
+pub(crate) fn parse_cursor(cursor: &str) -> anyhow::Result<(usize, &str)> {
+    let Some((offset, id)) = cursor.split_once(':') else {
+        bail!("invalid cursor {cursor:?}");
+    };
+    let offset = offset
+        .parse()
+        .map_err(|_| anyhow!("invalid cursor {cursor:?}"))?;
+
+    Ok((offset, id))
+}
+
+fn resume_at(items: &[AudioItem], cursor: &str) -> anyhow::Result<usize> {
+    let (offset, id) = parse_cursor(cursor)?;
+
+    Ok(items
+        .iter()
+        .position(|item| item.id == id)
+        .map_or(offset.min(items.len()), |pos| pos + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{query, RecordingsQuery, TranscriptionStatus};
+    use crate::audio::{
+        database::{Database, FSDatabase, SqliteDatabase},
+        polling::RecordingsSort,
+        search::SearchIndex,
+        AudioItem,
+    };
+
+    #[test]
+    fn it_pages_through_filtered_items() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = FSDatabase::open(dir.path().join("data.json"), dir.path()).unwrap();
+        for i in 0..7u64 {
+            let mut item = AudioItem::new(format!("item{i}"));
+            item.created_at = Some(i * 1000);
+            item.duration_ms = Some(i * 500);
+            item.label = (i != 3).then(|| format!(" Take number {i}."));
+            db.save_audio_item(item).unwrap();
+        }
+        let mut index = SearchIndex::default();
+        index.rebuild(&db.items());
+        let ids = |items: &[AudioItem]| -> Vec<String> {
+            items.iter().map(|item| item.id.clone()).collect()
+        };
+
+        let mut filter = RecordingsQuery {
+            min_duration_ms: Some(1000),
+            transcription: Some(TranscriptionStatus::Transcribed),
+            limit: Some(2),
+            ..Default::default()
+        };
+        let first = query(&db, &index, &filter).unwrap();
+        assert_eq!(ids(&first.items), ["item2", "item4"]);
+        assert_eq!(first.total, 4);
+
+        // items of earlier pages going away doesn't shift the next one
+        db.remove_item("item2").unwrap();
+        filter.cursor = first.next_cursor;
+        let second = query(&db, &index, &filter).unwrap();
+        assert_eq!(ids(&second.items), ["item5", "item6"]);
+        assert_eq!(second.next_cursor, None);
+
+        let found = query(
+            &db,
+            &index,
+            &RecordingsQuery {
+                text: Some("number 5".to_owned()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(ids(&found.items)[0], "item5");
+    }
+
+    #[test]
+    fn it_pages_the_same_through_sqlite() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut json = FSDatabase::open(dir.path().join("data.json"), dir.path()).unwrap();
+        let mut sqlite = SqliteDatabase::open(&dir.path().join("library.db")).unwrap();
+        let collection = json.create_collection("picks".to_owned()).unwrap();
+        let picks = sqlite.create_collection("picks".to_owned()).unwrap();
+        for i in 0..12u64 {
+            let mut item = AudioItem::new(format!("item{i:02}"));
+            item.created_at = (i % 5 != 0).then_some(i % 4 * 1000);
+            item.duration_ms = Some(i % 3 * 500);
+            item.rating = (i % 4 != 1).then_some((i % 6) as u8);
+            item.is_favourite = i % 3 == 0;
+            item.tags = if i % 2 == 0 {
+                vec!["even".to_owned()]
+            } else {
+                vec![]
+            };
+            item.label = (i != 7).then(|| format!(" Take {} of the day.", i % 3));
+            for db in [&mut json as &mut dyn Database, &mut sqlite] {
+                db.save_audio_item(item.clone()).unwrap();
+            }
+            if i % 4 == 2 {
+                json.add_to_collection(&collection.id, &item.id).unwrap();
+                sqlite.add_to_collection(&picks.id, &item.id).unwrap();
+            }
+        }
+        let mut index = SearchIndex::default();
+        index.rebuild(&json.items());
+
+        let all_pages = |db: &dyn Database, filter: &mut RecordingsQuery| {
+            filter.limit = Some(3);
+            filter.cursor = None;
+            let mut pages = Vec::new();
+            loop {
+                let page = query(db, &index, filter).unwrap();
+                let ids: Vec<String> = page.items.iter().map(|item| item.id.clone()).collect();
+                pages.push((ids, page.total));
+                match page.next_cursor {
+                    Some(cursor) => filter.cursor = Some(cursor),
+                    None => return pages,
+                }
+            }
+        };
+
+        let sorts = [
+            None,
+            Some(RecordingsSort::Oldest),
+            Some(RecordingsSort::Newest),
+            Some(RecordingsSort::Rating),
+            Some(RecordingsSort::FavouritesFirst),
+            Some(RecordingsSort::Longest),
+        ];
+        for sort in sorts {
+            let filters = [
+                RecordingsQuery::default(),
+                RecordingsQuery {
+                    text: Some("take 2".to_owned()),
+                    ..Default::default()
+                },
+                RecordingsQuery {
+                    tags: vec!["even".to_owned()],
+                    created_after: Some(1000),
+                    created_before: Some(3000),
+                    ..Default::default()
+                },
+                RecordingsQuery {
+                    min_duration_ms: Some(500),
+                    max_duration_ms: Some(500),
+                    transcription: Some(TranscriptionStatus::Transcribed),
+                    ..Default::default()
+                },
+                RecordingsQuery {
+                    favourites_only: true,
+                    min_rating: Some(2),
+                    ..Default::default()
+                },
+            ];
+            for filter in filters {
+                let mut filter = RecordingsQuery { sort, ..filter };
+                let expected = all_pages(&json, &mut filter);
+                assert_eq!(all_pages(&sqlite, &mut filter), expected, "{filter:?}");
+            }
+        }
+
+        let in_collection = |db: &dyn Database, id: &str| {
+            all_pages(
+                db,
+                &mut RecordingsQuery {
+                    collection: Some(id.to_owned()),
+                    ..Default::default()
+                },
+            )
+        };
+        assert_eq!(
+            in_collection(&json, &collection.id),
+            in_collection(&sqlite, &picks.id)
+        );
+        assert!(query(
+            &sqlite,
+            &index,
+            &RecordingsQuery {
+                collection: Some("nope".to_owned()),
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
+}
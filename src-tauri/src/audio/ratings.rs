@@ -0,0 +1,72 @@
+use anyhow::bail;
+
+use super::database::{Database, UpdateParams};
+
+pub const MAX_RATING: u8 = 5;
+
+pub fn set_favourite(db: &mut dyn Database, id: &str, is_favourite: bool) -> anyhow::Result<()> {
+    let updated = db.update_audio_items(UpdateParams {
+        id,
+        is_favourite: Some(is_favourite),
+        ..Default::default()
+    })?;
+    if !updated {
+        bail!("no audio item with id {id}");
+    }
+
+    Ok(())
+}
+
+/// Rates the item 1 to 5 stars, none takes its rating away.
+pub fn set_rating(db: &mut dyn Database, id: &str, rating: Option<u8>) -> anyhow::Result<()> {
+    if let Some(rating) = rating {
+        if !(1..=MAX_RATING).contains(&rating) {
+            bail!("ratings go from 1 to {MAX_RATING} stars, not {rating}");
+        }
+    }
+
+    let updated = db.update_audio_items(UpdateParams {
+        id,
+        rating: Some(rating),
+        ..Default::default()
+    })?;
+    if !updated {
+        bail!("no audio item with id {id}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audio::{polling::RecordingsSort, AudioItem};
+
+    #[test]
+    fn it_sorts_keepers_first() {
+        let take = |id: &str, created_at: u64, rating: Option<u8>, is_favourite: bool| {
+            let mut item = AudioItem::new(id.to_owned());
+            item.created_at = Some(created_at);
+            item.rating = rating;
+            item.is_favourite = is_favourite;
+            item
+        };
+        let mut items = vec![
+            take("a", 1, Some(3), false),
+            take("b", 2, None, true),
+            take("c", 3, Some(5), false),
+            take("d", 4, Some(3), false),
+        ];
+        let ids = |items: &[AudioItem]| -> Vec<String> {
+            items.iter().map(|item| item.id.clone()).collect()
+        };
+
+        RecordingsSort::Rating.sort(&mut items);
+        assert_eq!(ids(&items), ["c", "d", "a", "b"]);
+
+        RecordingsSort::FavouritesFirst.sort(&mut items);
+        assert_eq!(ids(&items), ["b", "d", "c", "a"]);
+
+        RecordingsSort::Oldest.sort(&mut items);
+        assert_eq!(ids(&items), ["a", "b", "c", "d"]);
+    }
+}
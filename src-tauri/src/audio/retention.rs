@@ -0,0 +1,368 @@
+//! Keeps the library within the limits of the retention policy in the settings.
+//!
+//! Favourite, tagged and playing items are never swept, being in a collection doesn't protect
+//! an item. Takes over any limit go to the trash and can be restored from there, a sweep never
+//! deletes a take that wasn't in the trash already. To get under the size limit the trash is
+//! purged, oldest deletion first, and the oldest takes are moved to the trash until the rest
+//! fit. Later sweeps purge them, if the library is still over the limit by then.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    sync::mpsc::RecvTimeoutError,
+    time::Duration,
+};
+
+use crate::{
+    background::procedure::BackgroundProcedure,
+    settings::{RetentionPolicy, SharedSettings},
+};
+
+use super::{
+    database::{Collection, SharedDatabase},
+    trash, unix_millis_now, AudioItem,
+};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Reason {
+    Age,
+    /// past the most recent takes of the named collection, and of any other it is in
+    CollectionLimit {
+        collection: String,
+    },
+    Quota,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Removal {
+    pub id: String,
+    pub label: Option<String>,
+    pub bytes: u64,
+    pub reason: Reason,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct SweepReport {
+    pub swept_at: u64,
+    /// moved to the trash
+    pub trashed: Vec<Removal>,
+    /// deleted for good
+    pub purged: Vec<Removal>,
+    /// bytes of audio left in the library, the trash included
+    pub library_bytes: u64,
+    /// by how much `library_bytes` is still over the size limit
+    pub over_quota_bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct Plan {
+    trash: Vec<Removal>,
+    purge: Vec<Removal>,
+    library_bytes: u64,
+}
+
+/// Sweeps the library every few minutes and whenever triggered, keeping the last report.
+pub fn setup(
+    db: SharedDatabase,
+    settings: SharedSettings,
+) -> BackgroundProcedure<Option<SweepReport>, ()> {
+    BackgroundProcedure::setup(None, move |arg| loop {
+        let policy = settings.lock().unwrap().retention.clone();
+        let report = sweep(&db, &policy);
+
+        if !report.trashed.is_empty() || !report.purged.is_empty() {
+            eprintln!(
+                "[info] retention sweep trashed {} and purged {} audio items",
+                report.trashed.len(),
+                report.purged.len()
+            );
+        }
+        *arg.state.lock().unwrap() = Some(report);
+
+        match arg.rx.recv_timeout(SWEEP_INTERVAL) {
+            Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    })
+}
+
+/// Applies `policy` now. Items that fail to be removed are logged and left out of the report.
+/// The database is only locked to take the items to plan on and item by item to remove them,
+/// not while files are looked at, moved or deleted.
+pub fn sweep(db: &SharedDatabase, policy: &RetentionPolicy) -> SweepReport {
+    let now = unix_millis_now();
+    let (mut live, mut trashed, collections) = {
+        let db = db.lock().unwrap();
+        (db.items(), db.trashed_items(), db.collections())
+    };
+    fill_in_file_sizes(&mut live);
+    fill_in_file_sizes(&mut trashed);
+    let plan = plan(&live, &trashed, &collections, policy, now);
+    let mut report = SweepReport {
+        swept_at: now,
+        library_bytes: plan.library_bytes,
+        ..Default::default()
+    };
+
+    for removal in plan.trash {
+        match trash::move_to_trash_shared(db, &removal.id, true) {
+            Ok(()) => report.trashed.push(removal),
+            Err(err) => eprintln!("[err] failed to sweep audio item {}: {err:#}", removal.id),
+        }
+    }
+
+    for removal in plan.purge {
+        match trash::purge_shared(db, &removal.id) {
+            Ok(()) => report.purged.push(removal),
+            Err(err) => {
+                eprintln!("[err] failed to purge audio item {}: {err:#}", removal.id);
+                report.library_bytes += removal.bytes;
+            }
+        }
+    }
+
+    if let Some(max) = policy.max_library_bytes {
+        report.over_quota_bytes = report.library_bytes.saturating_sub(max);
+    }
+
+    report
+}
+
+fn plan(
+    live: &[AudioItem],
+    trashed: &[AudioItem],
+    collections: &[Collection],
+    policy: &RetentionPolicy,
+    now: u64,
+) -> Plan {
+    let mut plan = Plan::default();
+    let mut swept: HashSet<&str> = HashSet::new();
+
+    if let Some(days) = policy.max_age_days {
+        let cutoff = now.saturating_sub(days as u64 * DAY_MILLIS);
+
+        for item in live {
+            if is_protected(item) {
+                continue;
+            }
+            if item.created_at.is_some_and(|at| at < cutoff) {
+                swept.insert(&item.id);
+                plan.trash.push(removal(item, Reason::Age));
+            }
+        }
+    }
+
+    if let Some(keep) = policy.keep_per_collection {
+        let by_id: HashMap<&str, &AudioItem> =
+            live.iter().map(|item| (item.id.as_str(), item)).collect();
+        let mut kept: HashSet<&str> = HashSet::new();
+        let mut over_limit: Vec<(&AudioItem, &str)> = vec![];
+
+        for collection in collections {
+            let mut takes: Vec<&AudioItem> = collection
+                .item_ids
+                .iter()
+                .filter_map(|id| by_id.get(id.as_str()).copied())
+                .collect();
+            takes.sort_by_key(|item| std::cmp::Reverse(age_key(item)));
+
+            let (recent, older) = takes.split_at(keep.min(takes.len()));
+            kept.extend(recent.iter().map(|item| item.id.as_str()));
+            over_limit.extend(older.iter().map(|item| (*item, collection.name.as_str())));
+        }
+
+        // an item stays as long as any of its collections keeps it
+        for (item, collection) in over_limit {
+            if kept.contains(item.id.as_str()) || is_protected(item) || !swept.insert(&item.id) {
+                continue;
+            }
+            plan.trash.push(removal(
+                item,
+                Reason::CollectionLimit {
+                    collection: collection.to_owned(),
+                },
+            ));
+        }
+    }
+
+    plan.library_bytes = live.iter().chain(trashed).map(size).sum();
+
+    if let Some(max) = policy.max_library_bytes {
+        let mut in_trash: Vec<&AudioItem> = trashed.iter().collect();
+        in_trash.sort_by_key(|item| item.deleted_at);
+        for item in in_trash {
+            if plan.library_bytes <= max {
+                break;
+            }
+            plan.purge.push(removal(item, Reason::Quota));
+            plan.library_bytes -= size(item);
+        }
+
+        // what goes to the trash now still takes up space until a later sweep purges it
+        let mut staying: u64 = live
+            .iter()
+            .filter(|item| !swept.contains(item.id.as_str()))
+            .map(size)
+            .sum();
+        let mut oldest: Vec<&AudioItem> = live
+            .iter()
+            .filter(|item| !is_protected(item) && !swept.contains(item.id.as_str()))
+            .collect();
+        oldest.sort_by_key(|item| age_key(item));
+        for item in oldest {
+            if plan.library_bytes <= max || staying <= max {
+                break;
+            }
+            plan.trash.push(removal(item, Reason::Quota));
+            staying -= size(item);
+        }
+    }
+
+    plan
+}
+
+/// Items from before sizes were recorded count with the size of their file, the metadata
+/// backfill may not have got to them yet.
+fn fill_in_file_sizes(items: &mut [AudioItem]) {
+    for item in items.iter_mut().filter(|item| item.file_size.is_none()) {
+        item.file_size = fs::metadata(&item.filepath).ok().map(|meta| meta.len());
+    }
+}
+
+/// Never swept by any limit.
+fn is_protected(item: &AudioItem) -> bool {
+    item.is_favourite || item.is_playing || !item.tags.is_empty()
+}
+
+/// Items of unknown age count as the most recent, so they are swept last.
+fn age_key(item: &AudioItem) -> u64 {
+    item.created_at.unwrap_or(u64::MAX)
+}
+
+fn size(item: &AudioItem) -> u64 {
+    item.file_size.unwrap_or(0)
+}
+
+fn removal(item: &AudioItem, reason: Reason) -> Removal {
+    Removal {
+        id: item.id.clone(),
+        label: item.label.clone(),
+        bytes: size(item),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{fill_in_file_sizes, plan, Reason, DAY_MILLIS};
+    use crate::{
+        audio::{database::Collection, AudioItem},
+        settings::RetentionPolicy,
+    };
+
+    fn take(id: &str, created_days_ago: u64, file_size: u64) -> AudioItem {
+        let mut item = AudioItem::new(id.to_owned());
+        item.created_at = Some(100 * DAY_MILLIS - created_days_ago * DAY_MILLIS);
+        item.file_size = Some(file_size);
+        item
+    }
+
+    #[test]
+    fn it_sweeps_unprotected_takes_by_age_collection_and_size() {
+        let now = 100 * DAY_MILLIS;
+        let mut tagged = take("tagged", 90, 10);
+        tagged.tags = vec!["keeper".to_owned()];
+        let mut playing = take("playing", 80, 10);
+        playing.is_playing = true;
+        let live = vec![
+            tagged,
+            playing,
+            take("old", 60, 10),
+            // over the age limit, but the only take of its collection
+            take("collected_stale", 45, 10),
+            take("collected_old", 25, 10),
+            take("collected_mid", 20, 10),
+            take("collected_new", 1, 10),
+            take("fresh", 2, 10),
+        ];
+        let mut in_trash = take("in_trash", 70, 10);
+        in_trash.deleted_at = Some(now - DAY_MILLIS);
+        let collections = vec![
+            Collection {
+                id: "c".to_owned(),
+                name: "Summer song".to_owned(),
+                item_ids: vec![
+                    "collected_old".to_owned(),
+                    "collected_mid".to_owned(),
+                    "collected_new".to_owned(),
+                ],
+            },
+            Collection {
+                id: "d".to_owned(),
+                name: "Winter song".to_owned(),
+                item_ids: vec!["collected_stale".to_owned()],
+            },
+        ];
+        let policy = RetentionPolicy {
+            max_library_bytes: Some(30),
+            max_age_days: Some(30),
+            keep_per_collection: Some(2),
+        };
+
+        let plan = plan(&live, &[in_trash], &collections, &policy, now);
+
+        let trashed: Vec<(&str, &Reason)> = plan
+            .trash
+            .iter()
+            .map(|r| (r.id.as_str(), &r.reason))
+            .collect();
+        let purged: Vec<(&str, &Reason)> = plan
+            .purge
+            .iter()
+            .map(|r| (r.id.as_str(), &r.reason))
+            .collect();
+        assert_eq!(
+            trashed,
+            vec![
+                ("old", &Reason::Age),
+                ("collected_stale", &Reason::Age),
+                (
+                    "collected_old",
+                    &Reason::CollectionLimit {
+                        collection: "Summer song".to_owned()
+                    }
+                ),
+                // the oldest of what is left, until the 30 bytes of the rest fit
+                ("collected_mid", &Reason::Quota),
+                ("fresh", &Reason::Quota),
+            ]
+        );
+        // only what was in the trash already is deleted for good
+        assert_eq!(purged, vec![("in_trash", &Reason::Quota)]);
+        assert_eq!(plan.library_bytes, 80);
+    }
+
+    #[test]
+    fn it_counts_items_without_a_recorded_size_by_their_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut legacy = take("legacy", 10, 0);
+        legacy.file_size = None;
+        legacy.filepath = dir.path().join("legacy.wav");
+        fs::write(&legacy.filepath, [0; 44]).unwrap();
+        let mut gone = take("gone", 10, 0);
+        gone.file_size = None;
+        gone.filepath = dir.path().join("gone.wav");
+        let mut items = vec![legacy, gone, take("recorded", 10, 10)];
+
+        fill_in_file_sizes(&mut items);
+
+        let sizes: Vec<Option<u64>> = items.iter().map(|item| item.file_size).collect();
+        assert_eq!(sizes, vec![Some(44), None, Some(10)]);
+    }
+}
@@ -0,0 +1,465 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use rust_stemmers::{Algorithm, Stemmer};
+
+use super::{
+    database::{Collection, Database, RecoveryReport, UpdateParams},
+    query::{RecordingsPage, RecordingsQuery},
+    AudioItem,
+};
+
+pub type SharedSearchIndex = Arc<Mutex<SearchIndex>>;
+
+const BM25_K1: f32 = 1.2;
+const BM25_B: f32 = 0.75;
+/// how much less a term matched with one edit counts than an exact match
+const FUZZY_PENALTY: f32 = 0.35;
+const SNIPPET_TOKENS: usize = 16;
+const SNIPPET_LEAD: usize = 4;
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SearchHit {
+    pub id: String,
+    pub score: f32,
+    /// html escaped text around the match, with the matched words wrapped in `<mark>`
+    pub snippet: String,
+}
+
+struct Token {
+    start: usize,
+    end: usize,
+    stem: String,
+}
+
+struct Document {
+    text: String,
+    tokens: Vec<Token>,
+}
+
+/// Inverted index over the text of the items that aren't in the trash.
+pub struct SearchIndex {
+    stemmer: Stemmer,
+    documents: HashMap<String, Document>,
+    /// stem -> item id -> term frequency
+    postings: HashMap<String, HashMap<String, u32>>,
+    total_tokens: usize,
+}
+
+impl Default for SearchIndex {
+    fn default() -> Self {
+        Self {
+            stemmer: Stemmer::create(Algorithm::English),
+            documents: HashMap::new(),
+            postings: HashMap::new(),
+            total_tokens: 0,
+        }
+    }
+}
+
+impl SearchIndex {
+    pub fn rebuild(&mut self, items: &[AudioItem]) {
+        self.documents.clear();
+        self.postings.clear();
+        self.total_tokens = 0;
+
+        for item in items {
+            self.upsert(item);
+        }
+    }
+
+    pub fn upsert(&mut self, item: &AudioItem) {
+        self.remove(&item.id);
+
+        if item.deleted_at.is_some() {
+            return;
+        }
+        let Some(text) = searchable_text(item) else {
+            return;
+        };
+
+        let tokens = self.tokenize(&text);
+        for token in tokens.iter() {
+            *self
+                .postings
+                .entry(token.stem.clone())
+                .or_default()
+                .entry(item.id.clone())
+                .or_default() += 1;
+        }
+        self.total_tokens += tokens.len();
+
+        self.documents
+            .insert(item.id.clone(), Document { text, tokens });
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        let Some(document) = self.documents.remove(id) else {
+            return;
+        };
+
+        self.total_tokens -= document.tokens.len();
+        for token in document.tokens {
+            if let Some(posting) = self.postings.get_mut(&token.stem) {
+                posting.remove(id);
+                if posting.is_empty() {
+                    self.postings.remove(&token.stem);
+                }
+            }
+        }
+    }
+
+    /// Ranks items by BM25 over the stemmed query terms, also matching terms within a small
+    /// edit distance since whisper often mishears words.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchHit> {
+        if self.documents.is_empty() {
+            return vec![];
+        }
+
+        let doc_count = self.documents.len() as f32;
+        let avg_len = self.total_tokens as f32 / doc_count;
+        let mut scores: HashMap<&str, f32> = HashMap::new();
+        let mut matched_stems: HashMap<&str, Vec<&str>> = HashMap::new();
+
+        for term in self.tokenize(query) {
+            for (stem, weight) in self.expand(&term.stem) {
+                let posting = &self.postings[stem];
+                let df = posting.len() as f32;
+                let idf = (1.0 + (doc_count - df + 0.5) / (df + 0.5)).ln();
+
+                for (id, tf) in posting {
+                    let len = self.documents[id].tokens.len() as f32;
+                    let tf = *tf as f32;
+                    let norm = tf * (BM25_K1 + 1.0)
+                        / (tf + BM25_K1 * (1.0 - BM25_B + BM25_B * len / avg_len));
+
+                    *scores.entry(id).or_default() += weight * idf * norm;
+                    matched_stems.entry(id).or_default().push(stem);
+                }
+            }
+        }
+
+        let mut hits: Vec<SearchHit> = scores
+            .into_iter()
+            .map(|(id, score)| SearchHit {
+                id: id.to_owned(),
+                score,
+                snippet: snippet(&self.documents[id], &matched_stems[id]),
+            })
+            .collect();
+
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.id.cmp(&b.id)));
+        hits.truncate(limit);
+        hits
+    }
+
+    /// Indexed stems that `stem` matches, with how much each match counts.
+    fn expand<'s>(&'s self, stem: &str) -> Vec<(&'s str, f32)> {
+        let max_distance = match stem.chars().count() {
+            0..=3 => 0,
+            4..=6 => 1,
+            _ => 2,
+        };
+
+        self.postings
+            .keys()
+            .filter_map(|indexed| {
+                if indexed == stem {
+                    return Some((indexed.as_str(), 1.0));
+                }
+                if max_distance == 0 {
+                    return None;
+                }
+
+                let distance = strsim::levenshtein(indexed, stem);
+                (distance <= max_distance)
+                    .then_some((indexed.as_str(), 1.0 - FUZZY_PENALTY * distance as f32))
+            })
+            .collect()
+    }
+
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        let mut tokens = vec![];
+        let mut start = None;
+
+        for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+            let is_word_char = c.is_alphanumeric() || c == '\'';
+
+            match (start, is_word_char) {
+                (None, true) => start = Some(i),
+                (Some(s), false) => {
+                    let word = text[s..i]
+                        .trim_matches('\'')
+                        .to_lowercase()
+                        .replace('\'', "");
+                    if !word.is_empty() {
+                        tokens.push(Token {
+                            start: s,
+                            end: i,
+                            stem: self.stemmer.stem(&word).into_owned(),
+                        });
+                    }
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        tokens
+    }
+}
+
+/// The title and the transcript.
+fn searchable_text(item: &AudioItem) -> Option<String> {
+    let parts: Vec<&str> = [item.title.as_deref(), item.label.as_deref()]
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect();
+
+    (!parts.is_empty()).then(|| parts.join(" — "))
+}
+
+fn snippet(document: &Document, matched_stems: &[&str]) -> String {
+    let tokens = &document.tokens;
+    let first_match = tokens
+        .iter()
+        .position(|t| matched_stems.contains(&t.stem.as_str()))
+        .unwrap_or(0);
+
+    let from = first_match.saturating_sub(SNIPPET_LEAD);
+    let to = (from + SNIPPET_TOKENS).min(tokens.len());
+
+    let mut snippet = String::new();
+    let mut cursor = 0;
+    if from > 0 {
+        snippet.push('…');
+        cursor = tokens[from].start;
+    }
+
+    for token in &tokens[from..to] {
+        escape_into(&mut snippet, &document.text[cursor..token.start]);
+        let word = &document.text[token.start..token.end];
+
+        if matched_stems.contains(&token.stem.as_str()) {
+            snippet.push_str("<mark>");
+            escape_into(&mut snippet, word);
+            snippet.push_str("</mark>");
+        } else {
+            escape_into(&mut snippet, word);
+        }
+        cursor = token.end;
+    }
+
+    if to < tokens.len() {
+        snippet.push('…');
+    } else {
+        escape_into(&mut snippet, &document.text[cursor..]);
+    }
+
+    snippet
+}
+
+fn escape_into(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+/// Wraps a database to keep the search index in step with every change made through it.
+pub struct Indexed {
+    inner: Box<dyn Database>,
+    index: SharedSearchIndex,
+}
+
+impl Indexed {
+    pub fn new(inner: Box<dyn Database>, index: SharedSearchIndex) -> Self {
+        index.lock().unwrap().rebuild(&inner.items());
+
+        Self { inner, index }
+    }
+
+    fn reindex(&self, id: &str) {
+        let mut index = self.index.lock().unwrap();
+        match self.inner.get(id) {
+            Some(item) => index.upsert(&item),
+            None => index.remove(id),
+        }
+    }
+}
+
+impl Database for Indexed {
+    fn reload(&mut self) -> anyhow::Result<bool> {
+        let reloaded = self.inner.reload()?;
+        if reloaded {
+            self.index.lock().unwrap().rebuild(&self.inner.items());
+        }
+        Ok(reloaded)
+    }
+
+    fn get(&self, id: &str) -> Option<AudioItem> {
+        self.inner.get(id)
+    }
+
+    fn items(&self) -> Vec<AudioItem> {
+        self.inner.items()
+    }
+
+    fn trashed_items(&self) -> Vec<AudioItem> {
+        self.inner.trashed_items()
+    }
+
+    fn remove_item(&mut self, id: &str) -> anyhow::Result<bool> {
+        let removed = self.inner.remove_item(id)?;
+        self.index.lock().unwrap().remove(id);
+        Ok(removed)
+    }
+
+    fn save_audio_item(&mut self, item: AudioItem) -> anyhow::Result<()> {
+        let id = item.id.clone();
+        self.inner.save_audio_item(item)?;
+        self.reindex(&id);
+        Ok(())
+    }
+
+    fn update_audio_items(&mut self, params: UpdateParams) -> anyhow::Result<bool> {
+        let id = params.id;
+        let affects_index =
+            params.label.is_some() || params.title.is_some() || params.deleted_at.is_some();
+
+        let updated = self.inner.update_audio_items(params)?;
+        if updated && affects_index {
+            self.reindex(id);
+        }
+
+        Ok(updated)
+    }
+
+    fn collections(&self) -> Vec<Collection> {
+        self.inner.collections()
+    }
+
+    fn create_collection(&mut self, name: String) -> anyhow::Result<Collection> {
+        self.inner.create_collection(name)
+    }
+
+    fn rename_collection(&mut self, id: &str, name: String) -> anyhow::Result<bool> {
+        self.inner.rename_collection(id, name)
+    }
+
+    fn delete_collection(&mut self, id: &str) -> anyhow::Result<bool> {
+        self.inner.delete_collection(id)
+    }
+
+    fn add_to_collection(&mut self, collection_id: &str, item_id: &str) -> anyhow::Result<bool> {
+        self.inner.add_to_collection(collection_id, item_id)
+    }
+
+    fn remove_from_collection(
+        &mut self,
+        collection_id: &str,
+        item_id: &str,
+    ) -> anyhow::Result<bool> {
+        self.inner.remove_from_collection(collection_id, item_id)
+    }
+
+    fn query_page(
+        &self,
+        query: &RecordingsQuery,
+        hits: Option<&[String]>,
+        limit: usize,
+    ) -> Option<anyhow::Result<RecordingsPage>> {
+        self.inner.query_page(query, hits, limit)
+    }
+
+    fn recovery_report(&self) -> Option<RecoveryReport> {
+        self.inner.recovery_report()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SearchIndex;
+    use crate::audio::AudioItem;
+
+    fn item(id: &str, label: &str) -> AudioItem {
+        let mut item = AudioItem::new(id.to_owned());
+        item.label = Some(label.to_owned());
+        item
+    }
+
+    fn index(items: &[AudioItem]) -> SearchIndex {
+        let mut index = SearchIndex::default();
+        index.rebuild(items);
+        index
+    }
+
+    #[test]
+    fn it_matches_stemmed_words() {
+        let index = index(&[
+            item("a", " I keep singing about the river."),
+            item("b", " Nothing to see here."),
+        ]);
+
+        let hits = index.search("sings", 10);
+
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, "a");
+        assert_eq!(
+            hits[0].snippet,
+            "I keep <mark>singing</mark> about the river."
+        );
+    }
+
+    #[test]
+    fn it_matches_misheard_words_below_exact_ones() {
+        let index = index(&[
+            item("exact", " Walking down the boulevard."),
+            item("misheard", " Walking down the bolevard."),
+        ]);
+
+        let hits = index.search("boulevard", 10);
+
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].id, "exact");
+        assert_eq!(hits[1].id, "misheard");
+        assert!(hits[0].score > hits[1].score);
+    }
+
+    #[test]
+    fn it_forgets_removed_and_relabeled_items() {
+        let mut index = index(&[item("a", " river song"), item("b", " river")]);
+
+        index.remove("b");
+        index.upsert(&item("a", " mountain song"));
+
+        assert!(index.search("river", 10).is_empty());
+        assert_eq!(index.search("mountain", 10)[0].id, "a");
+    }
+
+    #[test]
+    fn it_escapes_and_trims_snippets() {
+        let index = index(&[item(
+            "a",
+            "one two three four five six seven <eight> nine ten eleven twelve thirteen \
+             fourteen fifteen sixteen seventeen eighteen nineteen twenty",
+        )]);
+
+        let hits = index.search("nine", 10);
+
+        assert_eq!(
+            hits[0].snippet,
+            "…five six seven &lt;eight&gt; <mark>nine</mark> ten eleven twelve thirteen \
+             fourteen fifteen sixteen seventeen eighteen nineteen twenty"
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use anyhow::{anyhow, bail};
+
+use super::database::{Database, UpdateParams};
+
+/// Tags are trimmed and kept sorted without duplicates.
+pub fn add_tag(db: &mut dyn Database, id: &str, tag: &str) -> anyhow::Result<()> {
+    let tag = tag.trim();
+    if tag.is_empty() {
+        bail!("tags can't be empty");
+    }
+
+    let item = db
+        .get(id)
+        .ok_or_else(|| anyhow!("no audio item with id {id}"))?;
+    let mut tags = item.tags;
+    if let Err(index) = tags.binary_search_by(|t| t.as_str().cmp(tag)) {
+        tags.insert(index, tag.to_owned());
+    }
+
+    db.update_audio_items(UpdateParams {
+        id,
+        tags: Some(tags),
+        ..Default::default()
+    })?;
+
+    Ok(())
+}
+
+pub fn remove_tag(db: &mut dyn Database, id: &str, tag: &str) -> anyhow::Result<()> {
+    let item = db
+        .get(id)
+        .ok_or_else(|| anyhow!("no audio item with id {id}"))?;
+    let mut tags = item.tags;
+    tags.retain(|t| t != tag.trim());
+
+    db.update_audio_items(UpdateParams {
+        id,
+        tags: Some(tags),
+        ..Default::default()
+    })?;
+
+    Ok(())
+}
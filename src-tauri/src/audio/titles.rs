@@ -0,0 +1,104 @@
+//! Titles the user gives items. They are kept apart from the transcript in `label`, which
+//! transcribing rewrites, and every change is recorded in the item's history to revert to.
+
+use anyhow::anyhow;
+
+use super::{
+    database::{Database, UpdateParams},
+    unix_millis_now, AudioItem, TitleEdit,
+};
+
+/// Titles are trimmed, an empty or no title clears it and the transcript shows again.
+pub fn rename(db: &mut dyn Database, id: &str, title: Option<&str>) -> anyhow::Result<AudioItem> {
+    let title = title
+        .map(str::trim)
+        .filter(|title| !title.is_empty())
+        .map(str::to_owned);
+
+    set_title(db, id, title)
+}
+
+/// Gives the item the title of its `index`th edit again, as a new edit.
+pub fn revert(db: &mut dyn Database, id: &str, index: usize) -> anyhow::Result<AudioItem> {
+    let item = find(db, id)?;
+    let edit = item
+        .title_history
+        .get(index)
+        .ok_or_else(|| anyhow!("audio item {id} has no title edit {index}"))?;
+
+    set_title(db, id, edit.title.clone())
+}
+
+fn set_title(db: &mut dyn Database, id: &str, title: Option<String>) -> anyhow::Result<AudioItem> {
+    let item = find(db, id)?;
+    if item.title == title {
+        return Ok(item);
+    }
+
+    let mut history = item.title_history;
+    history.push(TitleEdit {
+        title: title.clone(),
+        edited_at: unix_millis_now(),
+    });
+
+    db.update_audio_items(UpdateParams {
+        id,
+        title: Some(title),
+        title_history: Some(history),
+        ..Default::default()
+    })?;
+
+    find(db, id)
+}
+
+fn find(db: &dyn Database, id: &str) -> anyhow::Result<AudioItem> {
+    db.get(id)
+        .ok_or_else(|| anyhow!("no audio item with id {id}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rename, revert};
+    use crate::audio::{
+        database::{Database, SqliteDatabase, UpdateParams},
+        AudioItem,
+    };
+
+    #[test]
+    fn it_keeps_titles_apart_from_transcripts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = SqliteDatabase::open(&dir.path().join("library.db")).unwrap();
+        db.save_audio_item(AudioItem::new_with_label(
+            "a".to_owned(),
+            " Hello there.".to_owned(),
+        ))
+        .unwrap();
+
+        rename(&mut db, "a", Some(" Verse one ")).unwrap();
+        rename(&mut db, "a", Some("Verse 1")).unwrap();
+        // transcribing again
+        db.update_audio_items(UpdateParams {
+            id: "a",
+            label: Some(" Hello there, again.".to_owned()),
+            ..Default::default()
+        })
+        .unwrap();
+        let item = revert(&mut db, "a", 0).unwrap();
+
+        assert_eq!(item.title.as_deref(), Some("Verse one"));
+        assert_eq!(item.label.as_deref(), Some(" Hello there, again."));
+        let titles: Vec<Option<&str>> = item
+            .title_history
+            .iter()
+            .map(|edit| edit.title.as_deref())
+            .collect();
+        assert_eq!(
+            titles,
+            vec![Some("Verse one"), Some("Verse 1"), Some("Verse one")]
+        );
+
+        let item = rename(&mut db, "a", Some("  ")).unwrap();
+        assert_eq!(item.title, None);
+        assert_eq!(item.title_history.len(), 4);
+    }
+}
@@ -0,0 +1,323 @@
+use std::{ffi::OsStr, fs, io, path::Path, thread, time::Duration};
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::settings::SharedSettings;
+
+use super::{
+    app_dir,
+    database::{Database, SharedDatabase, UpdateParams},
+    unix_millis_now, AudioItem,
+};
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+pub use super::library::trash_dir;
+
+/// Moves the item's audio into the trash dir and marks the item as deleted, with the database
+/// locked throughout.
+pub fn move_to_trash(db: &mut dyn Database, id: &str) -> anyhow::Result<()> {
+    let item = find(db, id)?;
+    if item.deleted_at.is_some() {
+        return Ok(());
+    }
+
+    let trashed_path = trash_dir().join(file_name(&item)?);
+    move_file(&item.filepath, &trashed_path)?;
+
+    let updated = db.update_audio_items(UpdateParams {
+        id,
+        filepath: Some(&trashed_path),
+        is_playing: Some(false),
+        deleted_at: Some(Some(unix_millis_now())),
+        ..Default::default()
+    });
+
+    if !matches!(updated, Ok(true)) {
+        move_file(&trashed_path, &item.filepath)?;
+    }
+    updated.context("failed to mark audio item as deleted")?;
+
+    Ok(())
+}
+
+/// Like [`move_to_trash`], but the shared database is only locked to look the item up and to
+/// mark it, not while its audio is moved. An item that changed meanwhile is left alone, as is
+/// one that is playing if `spare_playing`.
+pub fn move_to_trash_shared(
+    db: &SharedDatabase,
+    id: &str,
+    spare_playing: bool,
+) -> anyhow::Result<()> {
+    move_to_trash_in(db, id, &trash_dir(), spare_playing)
+}
+
+fn move_to_trash_in(
+    db: &SharedDatabase,
+    id: &str,
+    trash_dir: &Path,
+    spare_playing: bool,
+) -> anyhow::Result<()> {
+    let item = find(db.lock().unwrap().as_ref(), id)?;
+    if item.deleted_at.is_some() {
+        return Ok(());
+    }
+    if spare_playing && item.is_playing {
+        bail!("audio item {id} is playing");
+    }
+
+    let trashed_path = trash_dir.join(file_name(&item)?);
+    move_unlocked(
+        db,
+        &item,
+        &trashed_path,
+        UpdateParams {
+            id,
+            filepath: Some(&trashed_path),
+            is_playing: Some(false),
+            deleted_at: Some(Some(unix_millis_now())),
+            ..Default::default()
+        },
+    )
+    .context("failed to mark audio item as deleted")
+}
+
+/// Moves a trashed item's audio back into the library. The shared database is only locked to
+/// look the item up and to mark it, like in [`move_to_trash_shared`].
+pub fn restore_shared(db: &SharedDatabase, id: &str) -> anyhow::Result<()> {
+    restore_to(db, id, &app_dir())
+}
+
+fn restore_to(db: &SharedDatabase, id: &str, library_dir: &Path) -> anyhow::Result<()> {
+    let item = find(db.lock().unwrap().as_ref(), id)?;
+    if item.deleted_at.is_none() {
+        bail!("audio item {id} is not in the trash");
+    }
+
+    let restored_path = library_dir.join(file_name(&item)?);
+    move_unlocked(
+        db,
+        &item,
+        &restored_path,
+        UpdateParams {
+            id,
+            filepath: Some(&restored_path),
+            deleted_at: Some(None),
+            ..Default::default()
+        },
+    )
+    .context("failed to restore audio item")
+}
+
+/// Moves the audio of `item` to `to` without holding the lock, then applies `update` if the item
+/// is still where it was, moving the audio back otherwise.
+fn move_unlocked(
+    db: &SharedDatabase,
+    item: &AudioItem,
+    to: &Path,
+    update: UpdateParams,
+) -> anyhow::Result<()> {
+    move_file(&item.filepath, to)?;
+
+    let mut db = db.lock().unwrap();
+    let updated = match db.get(&item.id) {
+        Some(current)
+            if current.filepath == item.filepath
+                && current.deleted_at == item.deleted_at
+                && current.is_playing == item.is_playing =>
+        {
+            db.update_audio_items(update)
+        }
+        _ => Ok(false),
+    };
+
+    if !matches!(updated, Ok(true)) {
+        move_file(to, &item.filepath)?;
+    }
+    match updated {
+        Ok(true) => Ok(()),
+        Ok(false) => bail!("audio item {} changed while its audio was moved", item.id),
+        Err(err) => Err(err),
+    }
+}
+
+/// Deletes a trashed item and its audio for good. The shared database is only locked to remove
+/// the item, its audio is deleted after.
+pub fn purge_shared(db: &SharedDatabase, id: &str) -> anyhow::Result<()> {
+    let item = {
+        let mut db = db.lock().unwrap();
+        let item = find(db.as_ref(), id)?;
+        if item.deleted_at.is_none() {
+            bail!("audio item {id} is not in the trash");
+        }
+        db.remove_item(id)?;
+        item
+    };
+
+    match fs::remove_file(&item.filepath) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("failed to delete {:?}", item.filepath))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Purges every trashed item deleted before `retention` ago, returning their ids.
+pub fn purge_expired(db: &SharedDatabase, retention: Duration) -> Vec<String> {
+    let cutoff = unix_millis_now().saturating_sub(retention.as_millis() as u64);
+    let trashed = db.lock().unwrap().trashed_items();
+
+    trashed
+        .into_iter()
+        .filter(|item| item.deleted_at.is_some_and(|at| at < cutoff))
+        .filter_map(|item| match purge_shared(db, &item.id) {
+            Ok(()) => Some(item.id),
+            Err(err) => {
+                eprintln!("[err] failed to purge audio item {}: {err:#}", item.id);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Periodically purges items that outlived `trash_retention_days`.
+pub fn spawn_purger(db: SharedDatabase, settings: SharedSettings) {
+    thread::spawn(move || loop {
+        let retention_days = settings.lock().unwrap().trash_retention_days;
+
+        if retention_days > 0 {
+            let retention = Duration::from_millis(retention_days as u64 * DAY_MILLIS);
+            let purged = purge_expired(&db, retention);
+
+            if !purged.is_empty() {
+                eprintln!(
+                    "[info] purged {} expired items from the trash",
+                    purged.len()
+                );
+            }
+        }
+
+        thread::sleep(PURGE_INTERVAL);
+    });
+}
+
+fn find(db: &dyn Database, id: &str) -> anyhow::Result<AudioItem> {
+    db.get(id)
+        .ok_or_else(|| anyhow!("no audio item with id {id}"))
+}
+
+fn file_name(item: &AudioItem) -> anyhow::Result<&OsStr> {
+    item.filepath
+        .file_name()
+        .ok_or_else(|| anyhow!("audio item {} has no file name", item.id))
+}
+
+/// A missing source is fine, there is simply no audio to carry over.
+fn move_file(from: &Path, to: &Path) -> anyhow::Result<()> {
+    match fs::rename(from, to) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("failed to move {:?} to {:?}", from, to))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::Path,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{move_to_trash_in, purge_expired, restore_to, DAY_MILLIS};
+    use crate::audio::{
+        database::{Database, FSDatabase, SharedDatabase, SqliteDatabase},
+        unix_millis_now, AudioItem,
+    };
+
+    fn shared(db: Box<dyn Database>) -> SharedDatabase {
+        Arc::new(Mutex::new(db))
+    }
+
+    fn json(dir: &Path) -> Box<dyn Database> {
+        Box::new(FSDatabase::open(dir.join("data.json"), dir).unwrap())
+    }
+
+    fn sqlite(dir: &Path) -> Box<dyn Database> {
+        Box::new(SqliteDatabase::open(&dir.join("library.db")).unwrap())
+    }
+
+    /// Reopens the database for every step, so each has to have been persisted.
+    fn trash_and_restore(open: fn(&Path) -> Box<dyn Database>) {
+        let dir = tempfile::tempdir().unwrap();
+        let library = dir.path();
+        let trash = library.join("trash");
+        fs::create_dir(&trash).unwrap();
+        let mut item = AudioItem::new("a".to_owned());
+        item.filepath = library.join("a.wav");
+        fs::write(&item.filepath, b"RIFF").unwrap();
+        open(library).save_audio_item(item).unwrap();
+
+        move_to_trash_in(&shared(open(library)), "a", &trash, true).unwrap();
+        let db = open(library);
+        let trashed = db.get("a").unwrap();
+        assert!(trashed.deleted_at.is_some());
+        assert_eq!(trashed.filepath, trash.join("a.wav"));
+        assert!(trashed.filepath.is_file());
+        assert!(!library.join("a.wav").exists());
+        assert!(db.items().is_empty());
+        assert_eq!(db.trashed_items().len(), 1);
+        drop(db);
+
+        restore_to(&shared(open(library)), "a", library).unwrap();
+        let db = open(library);
+        let restored = db.get("a").unwrap();
+        assert_eq!(restored.deleted_at, None);
+        assert_eq!(restored.filepath, library.join("a.wav"));
+        assert!(restored.filepath.is_file());
+        assert!(!trash.join("a.wav").exists());
+        assert!(db.trashed_items().is_empty());
+    }
+
+    #[test]
+    fn it_trashes_and_restores_items_in_a_json_library() {
+        trash_and_restore(json);
+    }
+
+    #[test]
+    fn it_trashes_and_restores_items_in_a_sqlite_library() {
+        trash_and_restore(sqlite);
+    }
+
+    #[test]
+    fn it_only_purges_items_trashed_before_the_retention_period() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = shared(sqlite(dir.path()));
+        let now = unix_millis_now();
+        for (id, deleted_at) in [
+            ("expired", Some(now - 8 * DAY_MILLIS)),
+            ("recent", Some(now - 6 * DAY_MILLIS)),
+            ("live", None),
+        ] {
+            let mut item = AudioItem::new(id.to_owned());
+            item.filepath = dir.path().join(format!("{id}.wav"));
+            item.deleted_at = deleted_at;
+            fs::write(&item.filepath, b"RIFF").unwrap();
+            db.lock().unwrap().save_audio_item(item).unwrap();
+        }
+
+        let purged = purge_expired(&db, Duration::from_millis(7 * DAY_MILLIS));
+        let db = db.lock().unwrap();
+
+        assert_eq!(purged, vec!["expired".to_owned()]);
+        assert!(db.get("expired").is_none());
+        assert!(!dir.path().join("expired.wav").exists());
+        for id in ["recent", "live"] {
+            assert!(db.get(id).is_some());
+            assert!(dir.path().join(format!("{id}.wav")).is_file());
+        }
+    }
+}
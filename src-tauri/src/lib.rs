@@ -1,12 +1,35 @@
+pub mod atomicfile;
 pub mod audio;
 pub mod background;
+pub mod ringbuf;
+pub mod settings;
 pub mod sharedref;
 
-use audio::AudioCtrls;
+use std::{
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc, Mutex},
+};
+
+use audio::{database::Collection, AudioCtrls};
+use settings::{Settings, SharedSettings, MAX_PRE_ROLL_MS};
+use tauri::{Emitter, Manager};
+use tauri_plugin_dialog::{DialogExt, MessageDialogKind};
 
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 #[tauri::command]
-fn record_start(state: tauri::State<'_, AudioCtrls>) {
+fn record_start(
+    state: tauri::State<'_, AudioCtrls>,
+    settings: tauri::State<'_, SharedSettings>,
+) -> Result<(), String> {
+    // the recorder only logs a missing device, tell the user
+    let input = settings.lock().unwrap().input_device.clone();
+    audio::devices::input_device(input.as_deref()).map_err(|err| format!("{err:#}"))?;
+
+    // the recorder would ignore the new id, but the listener would transcribe under it
+    if let Some(take) = state.ecouter.state.lock().unwrap().as_ref() {
+        return Err(format!("take {} is already being recorded", take.id));
+    }
+
     let id = cuid2::cuid();
     state
         .ecouter
@@ -15,6 +38,7 @@ fn record_start(state: tauri::State<'_, AudioCtrls>) {
     state
         .sttlistener
         .trigger(audio::StreamControlCommand::Play(id));
+    Ok(())
 }
 
 #[tauri::command]
@@ -28,16 +52,131 @@ fn record_pause(state: tauri::State<'_, AudioCtrls>) {
         .trigger(audio::StreamControlCommand::Pause(None));
 }
 
+/// Pauses the take in progress without ending it, `record_resume` goes on with the same take.
+#[tauri::command]
+fn record_suspend(state: tauri::State<'_, AudioCtrls>) {
+    state.ecouter.trigger(audio::StreamControlCommand::Suspend);
+    state
+        .sttlistener
+        .trigger(audio::StreamControlCommand::Suspend);
+}
+
+#[tauri::command]
+fn record_resume(state: tauri::State<'_, AudioCtrls>) {
+    state.ecouter.trigger(audio::StreamControlCommand::Resume);
+    state
+        .sttlistener
+        .trigger(audio::StreamControlCommand::Resume);
+}
+
+/// Throws the take in progress away, nothing is saved or transcribed.
+#[tauri::command]
+fn record_cancel(state: tauri::State<'_, AudioCtrls>) {
+    state.ecouter.trigger(audio::StreamControlCommand::Cancel);
+    state
+        .sttlistener
+        .trigger(audio::StreamControlCommand::Cancel);
+}
+
 #[tauri::command]
 fn poll_recordings(
     state: tauri::State<'_, AudioCtrls>,
+    filter: Option<audio::polling::RecordingsFilter>,
 ) -> Result<audio::polling::RecordingsPoll, String> {
-    let result = audio::polling::RecordingsPoll::poll(&state.db.lock().unwrap())
-        .map_err(|err| err.to_string())?;
+    let result = audio::polling::RecordingsPoll::poll(
+        state.db.lock().unwrap().as_ref(),
+        &filter.unwrap_or_default(),
+    )
+    .map_err(|err| err.to_string())?;
     // eprintln!("[info] serving polled: {:?}", result);
     Ok(result)
 }
 
+/// Everything the recordings view shows, to start from or to resync after missing change
+/// events.
+#[tauri::command]
+fn recordings_snapshot(state: tauri::State<'_, AudioCtrls>) -> audio::events::Snapshot {
+    let db = state.db.lock().unwrap();
+    state.events.snapshot(db.as_ref())
+}
+
+/// One page of the items matching `query`, with the total count of them.
+#[tauri::command]
+fn query_recordings(
+    state: tauri::State<'_, AudioCtrls>,
+    query: audio::query::RecordingsQuery,
+) -> Result<audio::query::RecordingsPage, String> {
+    let db = state.db.lock().unwrap();
+    let search = state.search.lock().unwrap();
+
+    audio::query::query(db.as_ref(), &search, &query).map_err(|err| format!("{err:#}"))
+}
+
+/// Ranked by relevance, best match first.
+#[tauri::command]
+fn search_recordings(
+    state: tauri::State<'_, AudioCtrls>,
+    query: String,
+    limit: Option<usize>,
+) -> Vec<audio::search::SearchHit> {
+    state
+        .search
+        .lock()
+        .unwrap()
+        .search(&query, limit.unwrap_or(50))
+}
+
+/// Imports the audio files at `paths`, walking into directories, and queues them for
+/// transcription.
+#[tauri::command(async)]
+fn import_audio(
+    state: tauri::State<'_, AudioCtrls>,
+    paths: Vec<PathBuf>,
+) -> audio::import::ImportReport {
+    audio::import::import(&state.db, &state.transcriber.tx, &paths)
+}
+
+/// Exports the items with `ids`, or the whole library, to an archive at `dest`.
+#[tauri::command(async)]
+fn export_library(
+    state: tauri::State<'_, AudioCtrls>,
+    dest: PathBuf,
+    ids: Option<Vec<String>>,
+) -> Result<audio::archive::ExportReport, String> {
+    audio::archive::export(&state.db, ids.as_deref(), &dest).map_err(|err| format!("{err:#}"))
+}
+
+#[tauri::command(async)]
+fn import_library(
+    state: tauri::State<'_, AudioCtrls>,
+    archive: PathBuf,
+) -> Result<audio::archive::ArchiveImportReport, String> {
+    audio::archive::import(&state.db, &archive).map_err(|err| format!("{err:#}"))
+}
+
+/// Reports where item records and files disagree, fixing what it can if `repair` is set.
+#[tauri::command(async)]
+fn check_library(
+    state: tauri::State<'_, AudioCtrls>,
+    repair: bool,
+) -> audio::integrity::IntegrityReport {
+    let playing = state.player.state.lock().unwrap().clone();
+    let recording = state
+        .ecouter
+        .state
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|take| take.filepath.clone());
+    let mut report = audio::integrity::check(&state.db, playing.as_deref(), recording.as_deref());
+
+    if repair {
+        audio::integrity::repair(&state.db, &state.transcriber.tx, &mut report);
+    }
+
+    report
+}
+
 #[tauri::command]
 fn player_start(state: tauri::State<'_, AudioCtrls>, id: String) {
     state.player.trigger(audio::StreamControlCommand::Play(id));
@@ -51,21 +190,458 @@ fn player_pause(state: tauri::State<'_, AudioCtrls>, id: String) {
 }
 
 #[tauri::command]
-fn delete_item(state: tauri::State<'_, AudioCtrls>, id: String) {
-    state.db.lock().unwrap().remove_item(id)
+fn storage_recovery_report(
+    state: tauri::State<'_, AudioCtrls>,
+) -> Option<audio::database::RecoveryReport> {
+    state.db.lock().unwrap().recovery_report()
+}
+
+#[tauri::command(async)]
+fn delete_item(state: tauri::State<'_, AudioCtrls>, id: String) -> Result<(), String> {
+    audio::trash::move_to_trash_shared(&state.db, &id, false).map_err(|err| format!("{err:#}"))
+}
+
+#[tauri::command]
+fn list_trash(state: tauri::State<'_, AudioCtrls>) -> Vec<audio::AudioItem> {
+    state.db.lock().unwrap().trashed_items()
+}
+
+#[tauri::command(async)]
+fn restore_item(state: tauri::State<'_, AudioCtrls>, id: String) -> Result<(), String> {
+    audio::trash::restore_shared(&state.db, &id).map_err(|err| format!("{err:#}"))
+}
+
+/// Purges the given item from the trash, or all of the trash if no id is given.
+#[tauri::command(async)]
+fn purge_trash(state: tauri::State<'_, AudioCtrls>, id: Option<String>) -> Result<(), String> {
+    let ids = match id {
+        Some(id) => vec![id],
+        None => {
+            let trashed = state.db.lock().unwrap().trashed_items();
+            trashed.into_iter().map(|item| item.id).collect()
+        }
+    };
+
+    for id in ids {
+        audio::trash::purge_shared(&state.db, &id).map_err(|err| format!("{err:#}"))?;
+    }
+
+    Ok(())
+}
+
+/// Groups of items with identical audio.
+#[tauri::command]
+fn find_duplicates(state: tauri::State<'_, AudioCtrls>) -> Vec<audio::duplicates::DuplicateGroup> {
+    audio::duplicates::find(state.db.lock().unwrap().as_ref())
+}
+
+/// Merges `duplicates` into the item `keep`, moving them to the trash.
+#[tauri::command]
+fn merge_duplicates(
+    state: tauri::State<'_, AudioCtrls>,
+    keep: String,
+    duplicates: Vec<String>,
+) -> Result<audio::AudioItem, String> {
+    audio::duplicates::merge(state.db.lock().unwrap().as_mut(), &keep, &duplicates)
+        .map_err(|err| format!("{err:#}"))
+}
+
+/// Sets the title shown instead of the transcript, none or a blank one clears it.
+#[tauri::command]
+fn rename_item(
+    state: tauri::State<'_, AudioCtrls>,
+    id: String,
+    title: Option<String>,
+) -> Result<audio::AudioItem, String> {
+    audio::titles::rename(state.db.lock().unwrap().as_mut(), &id, title.as_deref())
+        .map_err(|err| format!("{err:#}"))
+}
+
+/// Brings back the title of the item's `index`th title edit.
+#[tauri::command]
+fn revert_title(
+    state: tauri::State<'_, AudioCtrls>,
+    id: String,
+    index: usize,
+) -> Result<audio::AudioItem, String> {
+    audio::titles::revert(state.db.lock().unwrap().as_mut(), &id, index)
+        .map_err(|err| format!("{err:#}"))
+}
+
+#[tauri::command]
+fn set_favourite(
+    state: tauri::State<'_, AudioCtrls>,
+    id: String,
+    favourite: bool,
+) -> Result<(), String> {
+    audio::ratings::set_favourite(state.db.lock().unwrap().as_mut(), &id, favourite)
+        .map_err(|err| format!("{err:#}"))
+}
+
+/// 1 to 5 stars, none clears the rating.
+#[tauri::command]
+fn set_rating(
+    state: tauri::State<'_, AudioCtrls>,
+    id: String,
+    rating: Option<u8>,
+) -> Result<(), String> {
+    audio::ratings::set_rating(state.db.lock().unwrap().as_mut(), &id, rating)
+        .map_err(|err| format!("{err:#}"))
+}
+
+#[tauri::command]
+fn add_tag(state: tauri::State<'_, AudioCtrls>, id: String, tag: String) -> Result<(), String> {
+    audio::tags::add_tag(state.db.lock().unwrap().as_mut(), &id, &tag)
+        .map_err(|err| format!("{err:#}"))
+}
+
+#[tauri::command]
+fn remove_tag(state: tauri::State<'_, AudioCtrls>, id: String, tag: String) -> Result<(), String> {
+    audio::tags::remove_tag(state.db.lock().unwrap().as_mut(), &id, &tag)
+        .map_err(|err| format!("{err:#}"))
+}
+
+#[tauri::command]
+fn list_collections(state: tauri::State<'_, AudioCtrls>) -> Vec<Collection> {
+    state.db.lock().unwrap().collections()
+}
+
+#[tauri::command]
+fn create_collection(
+    state: tauri::State<'_, AudioCtrls>,
+    name: String,
+) -> Result<Collection, String> {
+    state
+        .db
+        .lock()
+        .unwrap()
+        .create_collection(name)
+        .map_err(|err| format!("{err:#}"))
+}
+
+#[tauri::command]
+fn rename_collection(
+    state: tauri::State<'_, AudioCtrls>,
+    id: String,
+    name: String,
+) -> Result<(), String> {
+    match state.db.lock().unwrap().rename_collection(&id, name) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!("no collection with id {id}")),
+        Err(err) => Err(format!("{err:#}")),
+    }
+}
+
+#[tauri::command]
+fn delete_collection(state: tauri::State<'_, AudioCtrls>, id: String) -> Result<(), String> {
+    match state.db.lock().unwrap().delete_collection(&id) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!("no collection with id {id}")),
+        Err(err) => Err(format!("{err:#}")),
+    }
+}
+
+#[tauri::command]
+fn add_to_collection(
+    state: tauri::State<'_, AudioCtrls>,
+    collection_id: String,
+    item_id: String,
+) -> Result<(), String> {
+    match state
+        .db
+        .lock()
+        .unwrap()
+        .add_to_collection(&collection_id, &item_id)
+    {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!(
+            "no collection with id {collection_id} or no audio item with id {item_id}"
+        )),
+        Err(err) => Err(format!("{err:#}")),
+    }
+}
+
+#[tauri::command]
+fn remove_from_collection(
+    state: tauri::State<'_, AudioCtrls>,
+    collection_id: String,
+    item_id: String,
+) -> Result<(), String> {
+    state
+        .db
+        .lock()
+        .unwrap()
+        .remove_from_collection(&collection_id, &item_id)
+        .map(|_| ())
+        .map_err(|err| format!("{err:#}"))
+}
+
+#[tauri::command]
+fn get_settings(settings: tauri::State<'_, SharedSettings>) -> Settings {
+    settings.lock().unwrap().clone()
+}
+
+#[tauri::command]
+fn update_settings(
+    state: tauri::State<'_, AudioCtrls>,
+    settings: tauri::State<'_, SharedSettings>,
+    new_settings: Settings,
+) -> Result<(), String> {
+    if new_settings.pre_roll_ms > MAX_PRE_ROLL_MS {
+        return Err(format!(
+            "the pre-roll can be at most {} seconds",
+            MAX_PRE_ROLL_MS / 1000
+        ));
+    }
+    new_settings.save().map_err(|err| format!("{err:#}"))?;
+    let input = new_settings.input_device.clone();
+    let hands_free = new_settings.hands_free.clone();
+    let pre_roll_ms = new_settings.pre_roll_ms;
+    let old_settings = std::mem::replace(&mut *settings.lock().unwrap(), new_settings);
+    // apply a changed retention policy right away
+    state.sweeper.trigger(());
+    if old_settings.hands_free != hands_free && audio::handsfree::IS_ENABLED.load(Ordering::Relaxed)
+    {
+        state
+            .ecouter
+            .trigger(audio::StreamControlCommand::Monitor(Some(hands_free)));
+    }
+    if old_settings.pre_roll_ms != pre_roll_ms {
+        state
+            .ecouter
+            .trigger(audio::StreamControlCommand::PreRoll(pre_roll_ms));
+    }
+    if old_settings.input_device != input {
+        state
+            .ecouter
+            .trigger(audio::StreamControlCommand::UseInput(input.clone()));
+        state
+            .sttlistener
+            .trigger(audio::StreamControlCommand::UseInput(input));
+    }
+    Ok(())
+}
+
+/// Turns hands-free mode on or off, in which takes start when the input gets loud and end after
+/// a silence. Turning it off ends a take it started.
+#[tauri::command]
+fn set_hands_free(
+    state: tauri::State<'_, AudioCtrls>,
+    settings: tauri::State<'_, SharedSettings>,
+    enabled: bool,
+) -> Result<(), String> {
+    let settings = settings.lock().unwrap().clone();
+    if enabled {
+        audio::devices::input_device(settings.input_device.as_deref())
+            .map_err(|err| format!("{err:#}"))?;
+    }
+
+    audio::handsfree::IS_ENABLED.store(enabled, Ordering::Relaxed);
+    state.ecouter.trigger(audio::StreamControlCommand::Monitor(
+        enabled.then_some(settings.hands_free),
+    ));
+    Ok(())
+}
+
+#[tauri::command]
+fn is_hands_free() -> bool {
+    audio::handsfree::IS_ENABLED.load(Ordering::Relaxed)
+}
+
+#[tauri::command]
+fn list_input_devices() -> Result<Vec<audio::devices::InputDevice>, String> {
+    audio::devices::list().map_err(|err| format!("{err:#}"))
+}
+
+/// Records from the input device named `name` from now on, the system's default one if none.
+/// A take in progress is finished first.
+#[tauri::command]
+fn select_input_device(
+    state: tauri::State<'_, AudioCtrls>,
+    settings: tauri::State<'_, SharedSettings>,
+    name: Option<String>,
+) -> Result<(), String> {
+    audio::devices::input_device(name.as_deref()).map_err(|err| format!("{err:#}"))?;
+
+    let mut new_settings = settings.lock().unwrap().clone();
+    new_settings.input_device = name.clone();
+    new_settings.save().map_err(|err| format!("{err:#}"))?;
+    *settings.lock().unwrap() = new_settings;
+
+    state
+        .ecouter
+        .trigger(audio::StreamControlCommand::UseInput(name.clone()));
+    state
+        .sttlistener
+        .trigger(audio::StreamControlCommand::UseInput(name));
+    Ok(())
+}
+
+/// Applies the retention policy now, rather than waiting for the next background sweep.
+#[tauri::command(async)]
+fn sweep_library(
+    state: tauri::State<'_, AudioCtrls>,
+    settings: tauri::State<'_, SharedSettings>,
+) -> audio::retention::SweepReport {
+    let policy = settings.lock().unwrap().retention.clone();
+    let report = audio::retention::sweep(&state.db, &policy);
+    *state.sweeper.state.lock().unwrap() = Some(report.clone());
+    report
+}
+
+#[tauri::command]
+fn last_sweep_report(state: tauri::State<'_, AudioCtrls>) -> Option<audio::retention::SweepReport> {
+    state.sweeper.state.lock().unwrap().clone()
+}
+
+#[tauri::command]
+fn library_info() -> audio::library::LibraryInfo {
+    audio::library::info()
+}
+
+/// Opens the library at `path`, creating it if needed, and keeps using it on the next start.
+#[tauri::command(async)]
+fn switch_library(
+    state: tauri::State<'_, AudioCtrls>,
+    settings: tauri::State<'_, SharedSettings>,
+    path: PathBuf,
+) -> Result<(), String> {
+    audio::library::switch(&state, &path).map_err(|err| format!("{err:#}"))?;
+    remember_library(&settings, path)
+}
+
+/// Moves the library to `to`, the platform's data dir if none is given.
+#[tauri::command(async)]
+fn migrate_library(
+    state: tauri::State<'_, AudioCtrls>,
+    settings: tauri::State<'_, SharedSettings>,
+    to: Option<PathBuf>,
+) -> Result<audio::library::MigrationReport, String> {
+    let to = match to {
+        Some(to) => to,
+        None => audio::library::default_root().map_err(|err| format!("{err:#}"))?,
+    };
+
+    let report = audio::library::migrate(&state, &to).map_err(|err| format!("{err:#}"))?;
+    remember_library(&settings, to)?;
+
+    Ok(report)
+}
+
+fn remember_library(settings: &SharedSettings, dir: PathBuf) -> Result<(), String> {
+    let mut settings = settings.lock().unwrap();
+    // no setting for the default, so that it keeps following the platform's data dir
+    let is_default = audio::library::default_root().is_ok_and(|default| default == dir);
+    settings.library_dir = (!is_default).then_some(dir);
+
+    settings.save().map_err(|err| format!("{err:#}"))
+}
+
+/// Tells the user why the app can't start and quits once they've read it.
+fn report_library_in_use(app: &tauri::AppHandle, in_use: &audio::library::LibraryInUse) {
+    eprintln!("[err] {in_use}, close it or switch to it instead");
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.hide();
+    }
+
+    let handle = app.clone();
+    app.dialog()
+        .message(format!("{in_use}, close it or switch to it instead."))
+        .title("voEchoal can't open the library")
+        .kind(MessageDialogKind::Error)
+        .show(move |_| handle.exit(1));
 }
 
 pub fn run() {
+    let settings: SharedSettings = Arc::new(Mutex::new(Settings::load()));
+
     tauri::Builder::default()
+        // first, so that launching the app again brings up the running one before the second
+        // goes anywhere near the library
+        .plugin(tauri_plugin_single_instance::init(|app, _args, _cwd| {
+            if let Some(window) = app.get_webview_window("main") {
+                let _ = window.unminimize();
+                let _ = window.show();
+                let _ = window.set_focus();
+            }
+        }))
         .plugin(tauri_plugin_shell::init())
-        .manage(audio::setup().unwrap())
+        .plugin(tauri_plugin_dialog::init())
+        .manage(settings.clone())
+        .setup(move |app| {
+            let audio = match audio::setup(settings) {
+                Ok(audio) => audio,
+                Err(err) => {
+                    // held by something other than this app, e.g. another build of it
+                    if let Some(in_use) = err.downcast_ref::<audio::library::LibraryInUse>() {
+                        report_library_in_use(app.handle(), in_use);
+                        return Ok(());
+                    }
+                    return Err(format!("failed to set up audio: {err:#}").into());
+                }
+            };
+            app.manage(audio);
+
+            let handle = app.handle().clone();
+            app.state::<AudioCtrls>().events.set_sink(move |event| {
+                if let Err(err) = handle.emit(audio::events::CHANGE_EVENT, event) {
+                    eprintln!("[err] failed to emit change event: {err}");
+                }
+            });
+            let handle = app.handle().clone();
+            app.state::<AudioCtrls>().levels.set_sink(move |level| {
+                if let Err(err) = handle.emit(audio::meter::LEVEL_EVENT, level) {
+                    eprintln!("[err] failed to emit input level: {err}");
+                }
+            });
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             record_start,
             record_pause,
+            record_suspend,
+            record_resume,
+            record_cancel,
+            list_input_devices,
+            set_hands_free,
+            is_hands_free,
+            select_input_device,
             poll_recordings,
+            recordings_snapshot,
+            query_recordings,
+            search_recordings,
+            import_audio,
+            export_library,
+            import_library,
+            check_library,
+            library_info,
+            switch_library,
+            migrate_library,
             player_start,
             player_pause,
-            delete_item
+            delete_item,
+            storage_recovery_report,
+            list_trash,
+            restore_item,
+            purge_trash,
+            find_duplicates,
+            rename_item,
+            revert_title,
+            set_favourite,
+            set_rating,
+            merge_duplicates,
+            get_settings,
+            update_settings,
+            sweep_library,
+            last_sweep_report,
+            add_tag,
+            remove_tag,
+            list_collections,
+            create_collection,
+            rename_collection,
+            delete_collection,
+            add_to_collection,
+            remove_from_collection
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
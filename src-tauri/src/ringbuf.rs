@@ -0,0 +1,150 @@
+use std::sync::{
+    atomic::{AtomicU32, AtomicUsize, Ordering},
+    Arc,
+};
+
+/// A fixed size queue of `f32` samples from one producer to one consumer, neither of which
+/// ever waits on the other, so that an audio callback can hand samples off without locking.
+/// Samples that don't fit are dropped and counted, a push at a time so that whole frames stay
+/// whole.
+pub fn channel(capacity: usize) -> (Producer, Consumer) {
+    let shared = Arc::new(Shared {
+        slots: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        dropped: AtomicUsize::new(0),
+    });
+
+    (
+        Producer {
+            shared: Arc::clone(&shared),
+        },
+        Consumer { shared },
+    )
+}
+
+struct Shared {
+    /// samples as their bits, atomics make sharing them safe without locks
+    slots: Box<[AtomicU32]>,
+    /// count of samples ever popped, only the consumer moves it
+    head: AtomicUsize,
+    /// count of samples ever pushed, only the producer moves it
+    tail: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+impl Shared {
+    fn slot(&self, index: usize) -> &AtomicU32 {
+        &self.slots[index % self.slots.len()]
+    }
+}
+
+pub struct Producer {
+    shared: Arc<Shared>,
+}
+
+impl Producer {
+    /// Queues `samples` if they all fit, returns whether they did.
+    pub fn push(&mut self, samples: &[f32]) -> bool {
+        let shared = &self.shared;
+        let tail = shared.tail.load(Ordering::Relaxed);
+        let head = shared.head.load(Ordering::Acquire);
+        let free = shared.slots.len() - tail.wrapping_sub(head);
+
+        if samples.len() > free {
+            shared.dropped.fetch_add(samples.len(), Ordering::Relaxed);
+            return false;
+        }
+
+        for (i, sample) in samples.iter().enumerate() {
+            shared
+                .slot(tail.wrapping_add(i))
+                .store(sample.to_bits(), Ordering::Relaxed);
+        }
+        shared
+            .tail
+            .store(tail.wrapping_add(samples.len()), Ordering::Release);
+
+        true
+    }
+}
+
+pub struct Consumer {
+    shared: Arc<Shared>,
+}
+
+impl Consumer {
+    /// Takes up to `out.len()` samples off the queue into `out`, returns how many.
+    pub fn pop(&mut self, out: &mut [f32]) -> usize {
+        let shared = &self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        let tail = shared.tail.load(Ordering::Acquire);
+
+        let popped = out.len().min(tail.wrapping_sub(head));
+        for (i, sample) in out[..popped].iter_mut().enumerate() {
+            *sample = f32::from_bits(shared.slot(head.wrapping_add(i)).load(Ordering::Relaxed));
+        }
+        shared
+            .head
+            .store(head.wrapping_add(popped), Ordering::Release);
+
+        popped
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let shared = &self.shared;
+        shared.tail.load(Ordering::Acquire) == shared.head.load(Ordering::Relaxed)
+    }
+
+    /// Samples dropped because the queue was full since the last call.
+    pub fn take_dropped(&mut self) -> usize {
+        self.shared.dropped.swap(0, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::channel;
+
+    #[test]
+    fn it_drops_what_does_not_fit() {
+        let (mut producer, mut consumer) = channel(4);
+        let mut out = [0.0; 8];
+
+        assert!(producer.push(&[1.0, 2.0, 3.0]));
+        assert_eq!(consumer.pop(&mut out[..2]), 2);
+        assert_eq!(out[..2], [1.0, 2.0]);
+
+        assert!(!producer.push(&[4.0, 5.0, 6.0, 7.0]));
+        assert_eq!(consumer.take_dropped(), 4);
+        // wraps around the end of the slots
+        assert!(producer.push(&[4.0, 5.0, 6.0]));
+        assert_eq!(consumer.pop(&mut out), 4);
+        assert_eq!(out[..4], [3.0, 4.0, 5.0, 6.0]);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn it_hands_samples_across_threads_in_order() {
+        let (mut producer, mut consumer) = channel(64);
+
+        let writer = thread::spawn(move || {
+            let samples: Vec<f32> = (0..10_000).map(|i| i as f32).collect();
+            for frames in samples.chunks(16) {
+                while !producer.push(frames) {}
+            }
+        });
+
+        let mut received = vec![];
+        let mut out = [0.0; 32];
+        while received.len() < 10_000 {
+            let popped = consumer.pop(&mut out);
+            received.extend_from_slice(&out[..popped]);
+        }
+        writer.join().unwrap();
+
+        assert!(received.iter().enumerate().all(|(i, s)| *s == i as f32));
+    }
+}
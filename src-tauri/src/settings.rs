@@ -0,0 +1,115 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context};
+
+use crate::atomicfile;
+
+pub type SharedSettings = Arc<Mutex<Settings>>;
+
+/// The longest pre-roll, it is held in memory for as long as the app runs.
+pub const MAX_PRE_ROLL_MS: u32 = 30_000;
+
+/// User preferences, kept outside of the library in the platform's config directory.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// how long deleted items stay in the trash before they are purged, 0 keeps them forever
+    pub trash_retention_days: u32,
+    /// where the library lives, the platform's data dir if none
+    pub library_dir: Option<PathBuf>,
+    pub retention: RetentionPolicy,
+    /// name of the input device to record from, the system's default one if none
+    pub input_device: Option<String>,
+    pub hands_free: HandsFree,
+    /// how much input from before record is pressed each take begins with, 0 for none, up to
+    /// [`MAX_PRE_ROLL_MS`], keeps the input device running between takes
+    pub pre_roll_ms: u32,
+}
+
+/// When takes start and end in hands-free mode.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct HandsFree {
+    /// input level in dBFS above which a take starts
+    pub threshold_db: f32,
+    /// how long the input has to stay below the threshold for the take to end
+    pub silence_ms: u32,
+}
+
+impl Default for HandsFree {
+    fn default() -> Self {
+        Self {
+            threshold_db: -35.0,
+            silence_ms: 2000,
+        }
+    }
+}
+
+/// Limits the library is swept down to, none of them is enforced unless set.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    /// bytes of audio, the trash included
+    pub max_library_bytes: Option<u64>,
+    /// takes older than this are moved to the trash unless they are protected
+    pub max_age_days: Option<u32>,
+    /// only the most recent takes of each collection are kept
+    pub keep_per_collection: Option<usize>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            trash_retention_days: 30,
+            library_dir: None,
+            retention: RetentionPolicy::default(),
+            input_device: None,
+            hands_free: HandsFree::default(),
+            pre_roll_ms: 0,
+        }
+    }
+}
+
+impl Settings {
+    /// Falls back to the defaults if the settings file is missing or unreadable.
+    pub fn load() -> Self {
+        let Ok(path) = settings_file() else {
+            return Self::default();
+        };
+        let Ok(contents) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+
+        serde_json::from_str(&contents).unwrap_or_else(|err| {
+            eprintln!("[err] failed to parse settings file {:?}: {err}", path);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = settings_file()?;
+        let json_string =
+            serde_json::to_string_pretty(self).context("failed to serialize settings")?;
+
+        atomicfile::write(&path, json_string.as_bytes())
+            .context("failed to write settings file")?;
+
+        Ok(())
+    }
+}
+
+fn settings_file() -> anyhow::Result<PathBuf> {
+    let dir = dirs::config_dir()
+        .ok_or_else(|| anyhow!("failed to resolve the config directory"))?
+        .join("voechoal");
+
+    if !dir.is_dir() {
+        fs::create_dir_all(&dir).context("failed to create config dir")?;
+    }
+
+    Ok(dir.join("settings").with_extension("json"))
+}
@@ -3,9 +3,273 @@ export type AudioItem = {
   label: string | null;
   filepath: string;
   is_playing: boolean;
+  /** unix ms, set while the item is in the trash */
+  deleted_at: number | null;
+  /** unix ms */
+  created_at: number | null;
+  duration_ms: number | null;
+  sample_rate: number | null;
+  channels: number | null;
+  /** e.g. "f32" */
+  sample_format: string | null;
+  /** bytes */
+  file_size: number | null;
+  tags: string[];
+  /** name of the file the item was imported from */
+  original_filename: string | null;
+  /** the item's audio is gone or unreadable */
+  is_missing: boolean;
+  /** sha-256 of the audio, equal for identical recordings */
+  content_hash: string | null;
+  /** set by the user, shown instead of the transcript in `label` */
+  title: string | null;
+  /** oldest first, the last one is the current title */
+  title_history: TitleEdit[];
+  is_favourite: boolean;
+  /** 1 to 5 stars */
+  rating: number | null;
+  /** sample format the input device recorded in, e.g. "i16", null for imports */
+  input_format: string | null;
+  /** how much of the start of the take is from before recording was started */
+  pre_roll_ms: number | null;
 };
 
+export type TitleEdit = {
+  /** null if the title was cleared */
+  title: string | null;
+  /** unix ms */
+  edited_at: number;
+};
+
+export type Collection = {
+  id: string;
+  name: string;
+  item_ids: string[];
+};
+
+export type RecordingsFilter = {
+  tag?: string;
+  /** collection id */
+  collection?: string;
+  favourites_only?: boolean;
+  min_rating?: number;
+  /** oldest first, or in the collection's order, if unset */
+  sort?: RecordingsSort;
+};
+
+export type RecordingsSort =
+  | "oldest"
+  | "newest"
+  | "rating"
+  | "favourites_first"
+  | "longest";
+
 export type PollingState = {
   is_transcribing: boolean;
   audio_items: AudioItem[];
 };
+
+export type Settings = {
+  trash_retention_days: number;
+  /** where the library lives, the platform's data dir if null */
+  library_dir: string | null;
+  retention: RetentionPolicy;
+  /** name of the input device to record from, the system's default one if null */
+  input_device: string | null;
+  hands_free: HandsFree;
+  /** how much input from before record is pressed each take begins with, 0 for none */
+  pre_roll_ms: number;
+};
+
+/** when takes start and end in hands-free mode */
+export type HandsFree = {
+  /** input level in dBFS above which a take starts */
+  threshold_db: number;
+  /** how long the input has to stay below the threshold for the take to end */
+  silence_ms: number;
+};
+
+/** limits the library is swept down to, unset ones aren't enforced */
+export type RetentionPolicy = {
+  /** audio bytes, the trash included */
+  max_library_bytes: number | null;
+  max_age_days: number | null;
+  keep_per_collection: number | null;
+};
+
+export type InputDevice = {
+  name: string;
+  /** the system's default input */
+  is_default: boolean;
+  configs: InputConfig[];
+};
+
+export type InputConfig = {
+  channels: number;
+  min_sample_rate: number;
+  max_sample_rate: number;
+  /** e.g. "i16" */
+  sample_format: string;
+};
+
+export type Removal = {
+  id: string;
+  label: string | null;
+  bytes: number;
+  reason:
+    | { kind: "age" }
+    | { kind: "collection_limit"; collection: string }
+    | { kind: "quota" };
+};
+
+export type DuplicateGroup = {
+  content_hash: string;
+  /** oldest first */
+  ids: string[];
+};
+
+export type SweepReport = {
+  /** unix ms */
+  swept_at: number;
+  /** moved to the trash */
+  trashed: Removal[];
+  /** deleted for good */
+  purged: Removal[];
+  library_bytes: number;
+  /** by how much library_bytes is still over the size limit */
+  over_quota_bytes: number;
+};
+
+export type SearchHit = {
+  id: string;
+  score: number;
+  /** html escaped, with the matched words wrapped in `<mark>` */
+  snippet: string;
+};
+
+export type ImportReport = {
+  /** ids of the created items */
+  imported: string[];
+  failed: { path: string; reason: string }[];
+};
+
+export type ExportReport = {
+  items_exported: number;
+  /** ids of items left out because their audio couldn't be read */
+  skipped: string[];
+};
+
+export type ArchiveImportReport = {
+  imported: string[];
+  /** archive id -> new id */
+  renamed: Record<string, string>;
+  already_present: string[];
+  missing_audio: string[];
+};
+
+export type IntegrityIssue =
+  | { kind: "orphan_file"; path: string }
+  | { kind: "missing_file"; id: string; path: string; reason: string }
+  | { kind: "corrupt_header"; id: string; path: string; reason: string }
+  | { kind: "file_found"; id: string }
+  | { kind: "stale_playing"; id: string };
+
+export type IntegrityReport = {
+  items_checked: number;
+  findings: {
+    issue: IntegrityIssue;
+    repair:
+      | { status: "done"; action: string }
+      | { status: "failed"; reason: string }
+      | null;
+  }[];
+};
+
+export type LibraryInfo = {
+  root: string;
+  default_root: string | null;
+  /** the library still lives in ~/voechoal and can be migrated */
+  is_legacy: boolean;
+  /** VOECHOAL_LIBRARY decides the root at startup */
+  overridden_by_env: boolean;
+};
+
+export type MigrationReport = {
+  items_moved: number;
+  collections_moved: number;
+  files_left_in_place: string[];
+};
+
+/** what the recorder is doing, whether the take was started by hand or hands-free */
+export type RecordState =
+  | { status: "idle" }
+  | { status: "recording"; id: string }
+  | { status: "suspended"; id: string };
+
+export type Snapshot = {
+  /** the sequence number of the last change this snapshot includes */
+  seq: number;
+  is_transcribing: boolean;
+  record_state: RecordState;
+  audio_items: AudioItem[];
+  collections: Collection[];
+};
+
+export type Change =
+  | { type: "item_created"; item: AudioItem }
+  | { type: "item_updated"; item: AudioItem }
+  | { type: "item_removed"; id: string }
+  | { type: "label_updated"; id: string; label: string | null }
+  | { type: "playback_started"; id: string }
+  | { type: "playback_stopped"; id: string }
+  | { type: "transcription_started"; id: string }
+  | { type: "transcription_finished"; id: string }
+  | { type: "record_state_changed"; state: RecordState }
+  | { type: "collections_changed"; collections: Collection[] }
+  | { type: "library_switched" }
+  | { type: "reloaded" };
+
+/** payload of the "recordings-changed" event, `seq` increases by one per change */
+export type ChangeEvent = Change & { seq: number };
+
+export type RecordingsQuery = {
+  /** matched against titles and transcripts */
+  text?: string;
+  /** items must have all of them */
+  tags?: string[];
+  /** collection id */
+  collection?: string;
+  /** unix ms, inclusive */
+  created_after?: number;
+  /** unix ms, exclusive */
+  created_before?: number;
+  min_duration_ms?: number;
+  max_duration_ms?: number;
+  transcription?: "pending" | "transcribed";
+  favourites_only?: boolean;
+  min_rating?: number;
+  /** best match first with `text`, oldest first otherwise, if unset */
+  sort?: RecordingsSort;
+  /** `next_cursor` of the previous page */
+  cursor?: string;
+  /** 50 by default, at most 500 */
+  limit?: number;
+};
+
+export type RecordingsPage = {
+  items: AudioItem[];
+  /** of all pages */
+  total: number;
+  /** null on the last page */
+  next_cursor: string | null;
+};
+
+/** payload of the "input-level" event, 20 a second while recording, levels go from 0 to 1 */
+export type Level = {
+  rms: number;
+  peak: number;
+  /** some samples reached full scale */
+  clipped: boolean;
+  /** peaks of consecutive stretches of the input, oldest first */
+  waveform: number[];
+};